@@ -0,0 +1,36 @@
+use std::{fs, path::Path};
+
+// Hashes the level generator's sources into `LEVEL_GENERATOR_HASH`, cached levels built
+// by another version of the generator are rebuilt.
+fn main() {
+    let mut files = vec![Path::new("src/level.rs").to_path_buf()];
+    collect(Path::new("src/level"), &mut files);
+    files.sort();
+
+    let mut hash = 0xcbf29ce484222325_u64;
+    for file in &files {
+        println!("cargo:rerun-if-changed={}", file.display());
+        let bytes =
+            fs::read(file).unwrap_or_else(|err| panic!("failed to read {}: {err}", file.display()));
+        for byte in bytes {
+            hash = (hash ^ byte as u64).wrapping_mul(0x100000001b3);
+        }
+    }
+    println!("cargo:rerun-if-changed=src/level");
+    println!("cargo:rustc-env=LEVEL_GENERATOR_HASH={hash:016x}");
+}
+
+fn collect(dir: &Path, files: &mut Vec<std::path::PathBuf>) {
+    let entries =
+        fs::read_dir(dir).unwrap_or_else(|err| panic!("failed to list {}: {err}", dir.display()));
+    for entry in entries {
+        let path = entry
+            .unwrap_or_else(|err| panic!("failed to list {}: {err}", dir.display()))
+            .path();
+        if path.is_dir() {
+            collect(&path, files);
+        } else if path.extension().is_some_and(|ext| ext == "rs") {
+            files.push(path);
+        }
+    }
+}
@@ -0,0 +1,24 @@
+// Command line helpers shared by the game and `levelgen`.
+
+// Value following `name`, as in `--seed 42`.
+pub fn arg(name: &str) -> Option<String> {
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == name {
+            return Some(
+                args.next()
+                    .unwrap_or_else(|| panic!("{name} expects a value")),
+            );
+        }
+    }
+    None
+}
+
+// `--seed`, a random one if not given.
+pub fn seed() -> u64 {
+    match arg("--seed").map(|seed| seed.parse()) {
+        Some(Ok(seed)) => seed,
+        Some(Err(_)) => panic!("--seed expects an unsigned integer"),
+        None => rand::random(),
+    }
+}
@@ -0,0 +1,214 @@
+use std::{fs, path::Path};
+
+use bevy::prelude::*;
+use imageproc::{
+    drawing::{draw_filled_circle_mut, draw_hollow_rect_mut, draw_line_segment_mut},
+    image::{GrayImage, ImageBuffer, Luma, Rgb, RgbImage},
+    rect,
+};
+use petgraph::{algo::astar, graph::NodeIndex, visit::EdgeRef};
+use twg_2025_1::{
+    args::{self, arg},
+    level::{
+        Level, LevelBiome, LevelBuilder,
+        hazards::HazardKind,
+        layout::{self, LevelLayout},
+        run::RunRules,
+    },
+};
+
+fn main() {
+    let seed = args::seed();
+
+    let (source, builder) = if std::env::args().any(|arg| arg == "--run") {
+        let floor = match arg("--floor").map(|floor| floor.parse()) {
+            Some(Ok(floor)) => floor,
+            Some(Err(_)) => panic!("--floor expects an unsigned integer"),
+            None => 0,
+        };
+        let rules = RunRules::default();
+        let builder = LevelBuilder::random_run(rules.floor_seed(seed, floor), &rules, floor);
+        (format!("run floor {floor}"), builder)
+    } else {
+        let layout_path = arg("--layout").unwrap_or(layout::DEFAULT_PATH.to_string());
+        let layout = match LevelLayout::load(&layout_path) {
+            Ok(layout) => layout,
+            Err(err) => panic!("{layout_path}: {err}"),
+        };
+        let builder = LevelBuilder::from_layout(seed, &layout);
+        (layout_path, builder)
+    };
+
+    let out = arg("--out").unwrap_or("./levelgen".to_string());
+    let out = Path::new(&out);
+
+    let level = match builder.build(4.0) {
+        Ok(level) => level,
+        Err(err) => panic!("{source}: {err}"),
+    };
+
+    fs::create_dir_all(out).unwrap();
+    height_image(&level).save(out.join("height.png")).unwrap();
+    normal_image(&level).save(out.join("normal.png")).unwrap();
+    biome_image(&level).save(out.join("biome.png")).unwrap();
+    graph_image(&level).save(out.join("graph.png")).unwrap();
+
+    print_stats(&level);
+}
+
+fn height_image(level: &Level) -> GrayImage {
+    let heights = level.height_map();
+    let (min, max) = heights
+        .pixels()
+        .fold((f32::MAX, f32::MIN), |(min, max), pixel| {
+            (min.min(pixel.0[0]), max.max(pixel.0[0]))
+        });
+
+    ImageBuffer::from_fn(heights.width(), heights.height(), |x, y| {
+        let height = heights.get_pixel(x, y).0[0];
+        Luma([(255.0 * (height - min) / (max - min).max(f32::EPSILON)) as u8])
+    })
+}
+
+fn normal_image(level: &Level) -> RgbImage {
+    let normals = level.normal_map();
+    ImageBuffer::from_fn(normals.width(), normals.height(), |x, y| {
+        Rgb(normals
+            .get_pixel(x, y)
+            .0
+            .map(|n| (255.0 * (0.5 * n + 0.5).clamp(0.0, 1.0)) as u8))
+    })
+}
+
+fn biome_image(level: &Level) -> RgbImage {
+    let biomes = level.biome_map();
+    ImageBuffer::from_fn(biomes.width(), biomes.height(), |x, y| {
+        Rgb(biomes.get_pixel(x, y).color().map(|c| c as u8))
+    })
+}
+
+fn graph_image(level: &Level) -> RgbImage {
+    let heights = height_image(level);
+    let mut image = ImageBuffer::from_fn(heights.width(), heights.height(), |x, y| {
+        let [h] = heights.get_pixel(x, y).0;
+        Rgb([h, h, h])
+    });
+
+    for part in level.parts() {
+        let min = level.world_to_texture(part.bounds().min).as_ivec2();
+        let size = (level.world_to_texture(part.bounds().max).as_ivec2() - min).max(IVec2::ONE);
+        draw_hollow_rect_mut(
+            &mut image,
+            rect::Rect::at(min.x, min.y).of_size(size.x as u32, size.y as u32),
+            Rgb([0, 200, 200]),
+        );
+    }
+
+    for edge in level.graph.edge_references() {
+        let source = level.world_to_texture(level.graph[edge.source()]);
+        let target = level.world_to_texture(level.graph[edge.target()]);
+        draw_line_segment_mut(
+            &mut image,
+            (source.x, source.y),
+            (target.x, target.y),
+            Rgb([220, 40, 40]),
+        );
+    }
+
+    for hazard in level.hazards() {
+        let point = level.world_to_texture(hazard.pos).as_ivec2();
+        let radius = (hazard.radius / level.pixel_size()).round() as i32;
+        let color = match hazard.kind {
+            HazardKind::Acid => Rgb([150, 240, 40]),
+            HazardKind::Mud => Rgb([110, 70, 30]),
+            HazardKind::Spores => Rgb([190, 110, 240]),
+        };
+        draw_filled_circle_mut(&mut image, (point.x, point.y), radius, color);
+    }
+
+    for gate in level.gates() {
+        let (a, b) = gate.segment();
+        let (a, b) = (level.world_to_texture(a), level.world_to_texture(b));
+        draw_line_segment_mut(&mut image, (a.x, a.y), (b.x, b.y), Rgb([255, 60, 200]));
+    }
+
+    for point in level.graph.node_weights() {
+        let point = level.world_to_texture(*point).as_ivec2();
+        draw_filled_circle_mut(&mut image, (point.x, point.y), 3, Rgb([240, 220, 40]));
+    }
+
+    for prop in level.props() {
+        let point = level.world_to_texture(prop.pos).as_ivec2();
+        let radius = (prop.radius() / level.pixel_size()).round() as i32;
+        let color = if prop.obstacle {
+            Rgb([240, 120, 20])
+        } else {
+            Rgb([60, 160, 60])
+        };
+        draw_filled_circle_mut(&mut image, (point.x, point.y), radius, color);
+    }
+
+    image
+}
+
+fn print_stats(level: &Level) {
+    let bounds = level.bounds();
+    println!("seed: {}", level.seed());
+    println!("bounds: {} .. {}", bounds.min, bounds.max);
+    println!(
+        "nodes: {}, edges: {}",
+        level.graph.node_count(),
+        level.graph.edge_count()
+    );
+    println!(
+        "props: {}, obstacles: {}",
+        level.props().len(),
+        level.props().iter().filter(|prop| prop.obstacle).count()
+    );
+    println!("hazards: {}", level.hazards().len());
+    println!("gates: {}", level.gates().len());
+
+    for (idx, part) in level.parts().iter().enumerate() {
+        let bounds = part.bounds();
+        println!(
+            "part {idx}: {:?}, nodes {:?}, bounds {} .. {}",
+            part.biome(),
+            part.nodes(),
+            bounds.min,
+            bounds.max
+        );
+        if let Some(gate) = part.gate() {
+            println!("  gated: {gate:?}");
+        }
+    }
+
+    let part_nodes = |biome| {
+        level
+            .parts()
+            .iter()
+            .find(|part| part.biome() == biome)
+            .map(|part| part.nodes())
+    };
+
+    let (Some(mut home), Some(boss)) = (part_nodes(LevelBiome::Home), part_nodes(LevelBiome::Boss))
+    else {
+        println!("home -> boss: no home or boss part");
+        return;
+    };
+
+    let Some(start) = home.next() else {
+        println!("home -> boss: home part is empty");
+        return;
+    };
+
+    match astar(
+        &level.graph,
+        NodeIndex::new(start),
+        |node| boss.contains(&node.index()),
+        |edge| *edge.weight(),
+        |_| 0.0,
+    ) {
+        Some((length, path)) => println!("home -> boss: {length:.1} m, {} nodes", path.len()),
+        None => println!("home -> boss: unreachable"),
+    }
+}
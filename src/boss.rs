@@ -1,10 +1,12 @@
 use bevy::prelude::*;
 
 use crate::{
+    DeferDespawn, GameState,
+    faction::{self, Faction, Hostility, HostilityTable},
     model_loader::{LoadModel, ReadyAction},
     player::Player,
-    projectile::{Damage, Projectile, SpawnProjectile},
-    ui::UserNotify, GameState,
+    projectile::{Projectile, SpawnProjectile},
+    ui::UserNotify,
 };
 
 pub struct BossPlugin;
@@ -51,18 +53,23 @@ fn animate(
 fn update(
     mut commands: Commands,
     player: Single<(&mut Player, &Transform)>,
-    mut bosses: Query<(Entity, &mut Boss, &Transform)>,
+    mut bosses: Query<(Entity, &mut Boss, &Faction, &Transform)>,
     mut user_notify: EventWriter<UserNotify>,
-    projectiles: Query<(Entity, &Projectile, &Transform)>,
+    projectiles: Query<(Entity, &Projectile, &Faction, &Transform)>,
+    hostility: Res<HostilityTable>,
+    hostility_tables: Res<Assets<Hostility>>,
     time: Res<Time>,
     mut game_state: ResMut<GameState>,
 ) {
     let radius = 2.76 * 5.0;
+    let Some(hostility) = hostility.get(&hostility_tables) else {
+        return;
+    };
 
     let aggro_dist = 150.0;
     let (mut player, player_transform) = player.into_inner();
 
-    for (entity, mut boss, transform) in &mut bosses {
+    for (entity, mut boss, boss_faction, transform) in &mut bosses {
         boss.timer += time.delta_secs();
         let mut attack = false;
         if boss.timer >= boss.attack_delay {
@@ -72,9 +79,15 @@ fn update(
 
         let pos = transform.translation.xz().extend(40.0).xzy();
 
-        for (entity, projectile, transform) in projectiles {
-            if transform.translation.distance(pos) <= radius {
-                commands.entity(entity).despawn();
+        // the boss floats above the creature index, its hits are checked here
+        for (entity, projectile, faction, transform) in projectiles {
+            if transform.translation.distance(pos) <= radius
+                && hostility.hostile(faction, boss_faction)
+            {
+                commands
+                    .entity(entity)
+                    .remove::<Projectile>()
+                    .insert(DeferDespawn(projectile.particle_lifetime));
                 boss.hp -= projectile.damage;
                 let perc = (100.0 * boss.hp / boss.max_hp) as u32;
                 user_notify.write(UserNotify("Здоровье босса".to_string(), format!("{perc}%")));
@@ -97,7 +110,8 @@ fn update(
                 &mut commands,
                 Transform::from_translation(shoot_point)
                     .looking_at(player_transform.translation.xz().extend(1.7).xzy(), Vec3::Y),
-                Damage::Player,
+                Faction::new(faction::BOSS),
+                entity,
             );
         }
     }
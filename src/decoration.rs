@@ -0,0 +1,151 @@
+use std::f32::consts::TAU;
+
+use bevy::{prelude::*, render::view::VisibilityRange};
+use twg_2025_1::level::props::PropKind;
+
+pub struct DecorationPlugin;
+
+impl Plugin for DecorationPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, init);
+        app.add_systems(Update, setup);
+    }
+}
+
+// A prop scattered by the level, collision is already in the height map.
+#[derive(Component)]
+pub struct Decoration(pub PropKind);
+
+type Part = (Handle<Mesh>, Handle<StandardMaterial>, Transform);
+
+#[derive(Resource)]
+struct DecorationAssets {
+    parts: Vec<(PropKind, Vec<Part>)>,
+}
+
+impl DecorationAssets {
+    fn get(&self, kind: PropKind) -> &[Part] {
+        self.parts
+            .iter()
+            .find(|(other, _)| *other == kind)
+            .map_or(&[], |(_, parts)| parts)
+    }
+}
+
+fn init(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    let mut material = |color: Color| {
+        materials.add(StandardMaterial {
+            base_color: color,
+            perceptual_roughness: 0.9,
+            ..Default::default()
+        })
+    };
+    let bark = material(Color::srgb(0.25, 0.17, 0.1));
+    let leaves = material(Color::srgb(0.1, 0.3, 0.12));
+    let stone = material(Color::srgb(0.35, 0.33, 0.3));
+    let cave_stone = material(Color::srgb(0.25, 0.24, 0.28));
+    let stem = material(Color::srgb(0.75, 0.7, 0.6));
+    let cap = material(Color::srgb(0.5, 0.15, 0.4));
+    let marble = material(Color::srgb(0.6, 0.58, 0.52));
+    let bone = material(Color::srgb(0.8, 0.75, 0.6));
+
+    let rib = meshes.add(Capsule3d::new(0.12, 1.4));
+
+    let parts = PropKind::ALL
+        .into_iter()
+        .map(|kind| {
+            let parts = match kind {
+                PropKind::Tree => vec![
+                    (
+                        meshes.add(Cylinder::new(0.4, 4.0)),
+                        bark.clone(),
+                        Transform::from_xyz(0.0, 2.0, 0.0),
+                    ),
+                    (
+                        meshes.add(Sphere::new(2.0).mesh().ico(2).unwrap()),
+                        leaves.clone(),
+                        Transform::from_xyz(0.0, 5.0, 0.0),
+                    ),
+                ],
+                PropKind::Rock => vec![(
+                    meshes.add(Sphere::new(1.2).mesh().ico(1).unwrap()),
+                    stone.clone(),
+                    Transform::from_xyz(0.0, 0.3, 0.0).with_scale(Vec3::new(1.0, 0.6, 0.9)),
+                )],
+                PropKind::Stalagmite => vec![(
+                    meshes.add(Cone::new(1.0, 6.0)),
+                    cave_stone.clone(),
+                    Transform::from_xyz(0.0, 3.0, 0.0),
+                )],
+                PropKind::Fungus => vec![
+                    (
+                        meshes.add(Cylinder::new(0.3, 3.0)),
+                        stem.clone(),
+                        Transform::from_xyz(0.0, 1.5, 0.0),
+                    ),
+                    (
+                        meshes.add(Sphere::new(1.6)),
+                        cap.clone(),
+                        Transform::from_xyz(0.0, 3.0, 0.0).with_scale(Vec3::new(1.0, 0.4, 1.0)),
+                    ),
+                ],
+                PropKind::Pillar => vec![
+                    (
+                        meshes.add(Cylinder::new(0.9, 8.0)),
+                        marble.clone(),
+                        Transform::from_xyz(0.0, 4.0, 0.0),
+                    ),
+                    (
+                        meshes.add(Cuboid::new(2.4, 0.6, 2.4)),
+                        marble.clone(),
+                        Transform::from_xyz(0.0, 8.3, 0.0),
+                    ),
+                ],
+                PropKind::Bones => (0..3)
+                    .map(|idx| {
+                        let angle = idx as f32 * TAU / 6.0;
+                        (
+                            rib.clone(),
+                            bone.clone(),
+                            Transform::from_xyz(0.0, 0.15, 0.0).with_rotation(
+                                Quat::from_rotation_y(angle) * Quat::from_rotation_z(TAU / 4.0),
+                            ),
+                        )
+                    })
+                    .collect(),
+            };
+            (kind, parts)
+        })
+        .collect();
+
+    commands.insert_resource(DecorationAssets { parts });
+}
+
+fn setup(
+    mut commands: Commands,
+    assets: Res<DecorationAssets>,
+    decorations: Query<(Entity, &Decoration), Added<Decoration>>,
+) {
+    // props don't cast light, so far away ones are only wasted draw calls
+    let range = VisibilityRange::abrupt(0.0, 200.0);
+
+    for (entity, decoration) in decorations {
+        commands
+            .entity(entity)
+            .insert(Visibility::default())
+            .with_children(|parent| {
+                for (mesh, material, transform) in assets.get(decoration.0) {
+                    parent.spawn((
+                        Mesh3d(mesh.clone()),
+                        MeshMaterial3d(material.clone()),
+                        *transform,
+                        range.clone(),
+                    ));
+                }
+            });
+    }
+}
@@ -1,31 +1,37 @@
-use std::time::Duration;
+use std::{
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
 
 use bevy::{
-    math::bounding::Aabb3d,
+    math::bounding::{Aabb3d, BoundingVolume},
     pbr::{ExtendedMaterial, MaterialExtension},
     prelude::*,
     render::render_resource::{AsBindGroup, ShaderRef},
 };
-use petgraph::algo::astar;
+use petgraph::graph::NodeIndex;
+use rand::{Rng, rngs::StdRng, seq::IndexedRandom};
+use serde::Deserialize;
+use twg_2025_1::level::hazards::HazardKind;
 
 use crate::{
-    Bury, DeferDespawn,
+    Bury, DeferDespawn, GameRng,
+    enemy::{
+        archetype::{EnemyTable, EnemyTableLoader},
+        pack::{FLANK_SHARE, MELEE_SLOTS, PackAlert},
+        perception::{EYE_HEIGHT, Noise, Perception},
+    },
+    faction::{self, Faction, Hostility, HostilityTable},
+    hazard::HazardImmune,
     level::Level,
-    player::Player,
-    projectile::{ApplyDamage, Damage, SpawnProjectile},
+    projectile::{ApplyDamage, SpawnProjectile, apply_damage},
     terrain::Physics,
 };
 
-pub mod beetle;
-pub mod glutton;
-pub mod mushroom;
-pub mod seal;
-pub mod spider;
-pub mod stalker;
-pub mod tree;
-pub mod turret;
-pub mod wolf;
-pub mod wormbeak;
+pub mod aim;
+pub mod archetype;
+pub mod pack;
+pub mod perception;
 
 pub struct EnemyPlugin;
 
@@ -36,36 +42,119 @@ impl Plugin for EnemyPlugin {
         >::default());
 
         app.add_systems(Update, animate);
+        app.add_event::<Noise>();
+        app.add_event::<PackAlert>();
         app.add_systems(Update, ai);
+        app.add_systems(Update, perception::hear.before(ai));
+        app.add_systems(Update, pack::alert.after(ai));
         app.add_systems(Update, update_hp);
         app.add_systems(Update, update_lightmap);
-        app.add_systems(Update, beetle::setup);
-        app.add_systems(Update, glutton::setup);
-        app.add_systems(Update, mushroom::setup);
-        app.add_systems(Update, seal::setup);
-        app.add_systems(Update, spider::setup);
-        app.add_systems(Update, stalker::setup);
-        app.add_systems(Update, tree::setup);
-        app.add_systems(Update, turret::setup);
-        app.add_systems(Update, wolf::setup);
-        app.add_systems(Update, wormbeak::setup);
+        app.init_asset::<EnemyTable>();
+        app.init_asset_loader::<EnemyTableLoader>();
+        app.add_systems(Startup, archetype::init);
+        app.add_systems(
+            Update,
+            archetype::resolve.run_if(archetype::loaded.and(faction::loaded)),
+        );
     }
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Deserialize)]
 pub enum AttackKind {
     Ranged(SpawnProjectile),
     Melee(f32),
 }
 
+#[derive(Clone, Deserialize)]
+pub struct Attack {
+    pub kind: AttackKind,
+    #[serde(default)]
+    pub min_range: f32,
+    pub max_range: f32,
+    // Wind-up and strike together, the animation is stretched over it.
+    pub delay: f32,
+    // Time after the attack starts before it can be used again.
+    #[serde(default)]
+    pub cooldown: f32,
+    // Chance against the other attacks usable at the same distance.
+    #[serde(default = "Attack::default_weight")]
+    pub weight: f32,
+    // Clip of the model played for the attack.
+    #[serde(default = "Attack::default_animation")]
+    pub animation: String,
+}
+
+impl Attack {
+    fn default_weight() -> f32 {
+        1.0
+    }
+
+    fn default_animation() -> String {
+        "attack".to_string()
+    }
+
+    pub fn is_melee(&self) -> bool {
+        matches!(self.kind, AttackKind::Melee(_))
+    }
+}
+
+// What an archetype hands to the enemy once its model is loaded.
+#[derive(Clone)]
+pub struct EnemyStats {
+    pub attacks: Vec<Attack>,
+    pub speed: f32,
+    pub hp: f32,
+    // Share of the hp below which the enemy runs away once, 0 to always fight.
+    pub flee_below: f32,
+    pub accuracy: f32,
+}
+
+impl EnemyStats {
+    // Animations the model must have, in the order of the animation graph's nodes.
+    pub fn clips(&self) -> Vec<&str> {
+        let mut clips = vec!["idle", "walk", "attack", "death"];
+        for attack in &self.attacks {
+            if !clips.contains(&attack.animation.as_str()) {
+                clips.push(&attack.animation);
+            }
+        }
+        clips
+    }
+}
+
 #[derive(Debug, Clone)]
 enum State {
-    Idle,
+    Idle {
+        wait: f32,
+    },
+    // Wandering between graph nodes around `Enemy::home`.
+    Patrol {
+        target: NodeIndex,
+    },
     Walk {
         aggro_timer: f32,
         aggro_entity: Entity,
+        last_seen: Vec2,
+        // Side of the target a pack member comes from, `None` heads straight at it.
+        flank: Option<Vec2>,
+    },
+    // Heading to where the target was last seen, then looking around until `timer` runs out.
+    Search {
+        path: Vec<NodeIndex>,
+        timer: f32,
+    },
+    Flee {
+        timer: f32,
+        threat: Entity,
+    },
+    // Turns on `attacker` afterwards if it is a foe.
+    Stagger {
+        timer: f32,
+        attacker: Option<Entity>,
     },
     Attack {
+        // Index into `Enemy::attacks`.
+        attack: usize,
         timer_prepare: f32,
         timer_action: f32,
         damage_done: bool,
@@ -79,7 +168,11 @@ enum State {
 enum Animation {
     Idle,
     Walk,
-    Attack,
+    Patrol,
+    Search,
+    Flee,
+    Stagger,
+    Attack(usize),
     Death,
 }
 
@@ -87,11 +180,20 @@ enum Animation {
 pub struct Enemy {
     scene: Entity,
     anim_player: Entity,
-    attack: AttackKind,
-    attack_range: f32,
-    attack_delay: f32,
+    attacks: Vec<Attack>,
+    // Time left before each attack can be used again.
+    cooldowns: Vec<f32>,
+    // Animation graph node of each attack's clip.
+    attack_nodes: Vec<AnimationNodeIndex>,
     speed: f32,
     hp: f32,
+    max_hp: f32,
+    flee_below: f32,
+    // 1.0 leads moving targets fully and hits, lower misses more.
+    accuracy: f32,
+    fled: bool,
+    // Where the enemy first stood, patrols stay around it.
+    home: Option<Vec2>,
     shoot_point: Vec3,
     state: State,
     animation: Option<Animation>,
@@ -101,26 +203,47 @@ impl Enemy {
     pub fn new(
         scene: Entity,
         anim_player: Entity,
-        attack: AttackKind,
-        attack_range: f32,
-        attack_delay: f32,
-        speed: f32,
-        hp: f32,
+        stats: EnemyStats,
         shoot_point: Vec3,
+        attack_nodes: Vec<AnimationNodeIndex>,
     ) -> Self {
         Self {
             scene,
             anim_player,
-            attack,
-            attack_range,
-            attack_delay,
-            speed,
-            hp,
+            cooldowns: vec![0.0; stats.attacks.len()],
+            attacks: stats.attacks,
+            attack_nodes,
+            speed: stats.speed,
+            hp: stats.hp,
+            max_hp: stats.hp,
+            flee_below: stats.flee_below,
+            accuracy: stats.accuracy,
+            fled: false,
+            home: None,
             shoot_point,
-            state: State::Idle,
+            state: State::Idle { wait: 0.0 },
             animation: None,
         }
     }
+
+    fn max_range(&self) -> f32 {
+        self.attacks
+            .iter()
+            .map(|attack| attack.max_range)
+            .fold(0.0, f32::max)
+    }
+
+    // Attacks ready to be used at `dist` from the target.
+    fn usable_attacks(&self, dist: f32, melee: bool) -> Vec<usize> {
+        (0..self.attacks.len())
+            .filter(|idx| {
+                let attack = &self.attacks[*idx];
+                self.cooldowns[*idx] <= 0.0
+                    && (attack.min_range..=attack.max_range).contains(&dist)
+                    && (melee || !attack.is_melee())
+            })
+            .collect()
+    }
 }
 
 fn update_lightmap(
@@ -129,12 +252,28 @@ fn update_lightmap(
     for _ in materials.iter_mut() {}
 }
 
-fn update_hp(mut commands: Commands, mut enemies: Query<(Entity, &mut Enemy, &ApplyDamage)>) {
-    for (entity, mut enemy, damage) in &mut enemies {
-        commands.entity(entity).remove::<ApplyDamage>();
-        enemy.hp -= damage.0;
+// How long an enemy keeps chasing a target it can't see.
+const AGGRO_TIMER: f32 = 5.0;
+
+// Taking this share of the max hp at once interrupts the enemy for a moment,
+// hazards hurt without staggering.
+const STAGGER_DAMAGE: f32 = 0.2;
+const STAGGER_TIME: f32 = 0.4;
+
+fn update_hp(mut enemies: Query<(&mut Enemy, &mut ApplyDamage), Changed<ApplyDamage>>) {
+    for (mut enemy, mut pending) in &mut enemies {
+        let damage = std::mem::take(pending.bypass_change_detection());
+        enemy.hp -= damage.damage;
         if enemy.hp <= 0.0 {
             enemy.state = State::Death;
+        } else if damage.source.is_some()
+            && damage.damage >= STAGGER_DAMAGE * enemy.max_hp
+            && !matches!(enemy.state, State::Stagger { .. })
+        {
+            enemy.state = State::Stagger {
+                timer: STAGGER_TIME,
+                attacker: damage.source,
+            };
         }
     }
 }
@@ -152,25 +291,34 @@ fn animate(
 ) {
     let idle = AnimationNodeIndex::new(1);
     let walk = AnimationNodeIndex::new(2);
-    let attack = AnimationNodeIndex::new(3);
     let death = AnimationNodeIndex::new(4);
 
     for (entity, mut enemy, physics) in &mut enemies {
         let (mut player, mut transition, graph) = animation.get_mut(enemy.anim_player).unwrap();
 
-        let index = match enemy.animation.take() {
-            Some(Animation::Idle) => idle,
-            Some(Animation::Walk) => walk,
-            Some(Animation::Attack) => attack,
-            Some(Animation::Death) => {
+        let Some(animation) = enemy.animation.take() else {
+            continue;
+        };
+        let index = match animation {
+            Animation::Idle | Animation::Search => idle,
+            Animation::Walk | Animation::Patrol | Animation::Flee => walk,
+            Animation::Attack(attack) => enemy.attack_nodes[attack],
+            // the corpse lies around for a while, no one's foe anymore
+            Animation::Death => {
                 commands
                     .entity(entity)
                     .remove::<Enemy>()
-                    .remove::<Physics>();
+                    .remove::<Physics>()
+                    .remove::<Faction>();
                 death
             }
-            _ => continue,
+            // freezes whatever was playing, the next state resumes it
+            Animation::Stagger => {
+                player.pause_all();
+                continue;
+            }
         };
+        player.resume_all();
 
         let AnimationNodeType::Clip(clip) =
             &graphs.get(graph).unwrap().get(index).unwrap().node_type
@@ -179,16 +327,24 @@ fn animate(
         };
         let clip = clips.get(clip).unwrap();
 
+        let speed = match animation {
+            Animation::Idle | Animation::Death => 1.0,
+            // looking around
+            Animation::Search => 1.5,
+            Animation::Walk | Animation::Patrol | Animation::Flee => {
+                clip.duration() * physics.speed * 0.5
+            }
+            Animation::Attack(attack) => clip.duration() / enemy.attacks[attack].delay,
+            Animation::Stagger => unreachable!(),
+        };
+
         if !player.is_playing_animation(index) || player.all_finished() {
             transition
                 .play(&mut player, index, Duration::from_millis(100))
-                .set_speed(match true {
-                    _ if index == idle => 1.0,
-                    _ if index == walk => clip.duration() * physics.speed * 0.5,
-                    _ if index == attack => clip.duration() / enemy.attack_delay,
-                    _ if index == death => 1.0,
-                    _ => unreachable!(),
-                });
+                .set_speed(speed);
+        } else if let Some(active) = player.animation_mut(index) {
+            // patrols and flights share the walk clip at different speeds
+            active.set_speed(speed);
         }
     }
 }
@@ -219,55 +375,239 @@ fn aabb_segment_intersection(aabb: Aabb3d, segment: Segment3d) -> bool {
         && aabb_ray_intersection(aabb, Ray3d::new(segment.point2(), -segment.direction()))
 }
 
+// Heads for the furthest of `points` that can be walked to in a straight line. The first
+// one may cross hazards, the path leading to it already went around the ones it could.
+fn steer(
+    level: &Level,
+    physics: &mut Physics,
+    pos: Vec2,
+    points: impl IntoIterator<Item = Vec2>,
+    immune: &[HazardKind],
+    delta: f32,
+) {
+    for (idx, target) in points.into_iter().enumerate() {
+        if level.can_walk(pos, target, physics.radius - 0.001)
+            && (idx == 0 || !level.crosses_hazard(pos, target, immune))
+        {
+            physics.move_vec = target - pos;
+        }
+    }
+
+    physics.look_to = physics.look_to.slerp(
+        Dir2::new(-physics.move_vec).unwrap_or(Dir2::NEG_Y),
+        delta * 10.0,
+    );
+}
+
+// A random node next to the one the enemy stands at, keeping close to `home` or
+// heading back to it after a chase.
+fn patrol_node(
+    level: &Level,
+    pos: Vec2,
+    home: Vec2,
+    radius: f32,
+    rng: &mut StdRng,
+) -> Option<NodeIndex> {
+    let node = level.nearest_id_terrain(1, pos)[0];
+    let neighbours = level
+        .graph
+        .neighbors(node)
+        .filter(|next| level.can_walk(level.graph[node], level.graph[*next], 0.5))
+        .collect::<Vec<_>>();
+    let near_home = neighbours
+        .iter()
+        .copied()
+        .filter(|next| level.graph[*next].distance(home) <= radius)
+        .collect::<Vec<_>>();
+
+    near_home.choose(rng).copied().or_else(|| {
+        neighbours.into_iter().min_by(|a, b| {
+            let (a, b) = (level.graph[*a], level.graph[*b]);
+            a.distance(home).total_cmp(&b.distance(home))
+        })
+    })
+}
+
 fn ai(
     mut commands: Commands,
-    level: Res<Level>,
-    player: Single<Entity, With<Player>>,
+    mut level: ResMut<Level>,
+    mut rng: ResMut<GameRng>,
+    hostility: Res<HostilityTable>,
+    hostility_tables: Res<Assets<Hostility>>,
+    factions: Query<&Faction>,
     transforms: Query<&Transform>,
     global_transforms: Query<&GlobalTransform>,
-    mut enemies: Query<(Entity, &mut Enemy)>,
+    mut enemies: Query<(Entity, &mut Enemy, &Perception, Option<&HazardImmune>)>,
     mut all_physics: Query<&mut Physics>,
     time: Res<Time>,
 ) {
-    let default_aggro_distance = 25.0;
-    let default_aggro_timer = 5.0;
-
-    let player_pos = transforms.get(*player).unwrap().translation.xz();
+    let waypoint_lookahead = 8;
+    let waypoint_reached = 1.5;
+    let patrol_radius = 20.0;
+    let patrol_speed = 0.4;
+    let search_timer = 10.0;
+    let flee_timer = 5.0;
+    let flee_speed = 1.3;
+
+    let Some(hostility) = hostility.get(&hostility_tables) else {
+        return;
+    };
+
+    let delta = time.delta_secs();
+    // the pack around the enemy joins whenever it turns on a target
+    let aggro = |commands: &mut Commands, enemy: Entity, aggro_entity: Entity, last_seen: Vec2| {
+        commands.send_event(PackAlert {
+            enemy,
+            target: aggro_entity,
+            last_seen,
+        });
+        State::Walk {
+            aggro_timer: AGGRO_TIMER,
+            aggro_entity,
+            last_seen,
+            flank: None,
+        }
+    };
+    let idle = |rng: &mut StdRng| State::Idle {
+        wait: rng.random_range(2.0..6.0),
+    };
+
+    // melee attackers per target, the others wait for a free slot
+    let mut melee_slots = HashMap::<Entity, usize>::new();
+    // dying enemies are no one's target anymore
+    let mut dying = HashSet::new();
+    for (entity, enemy, ..) in &enemies {
+        if let State::Death = enemy.state {
+            dying.insert(entity);
+        }
+        if let State::Attack { target, attack, .. } = enemy.state
+            && enemy.attacks[attack].is_melee()
+        {
+            *melee_slots.entry(target).or_default() += 1;
+        }
+    }
 
-    for (entity, mut enemy) in &mut enemies {
+    for (entity, mut enemy, perception, immune) in &mut enemies {
+        let immune = immune.map_or(&[][..], |immune| &immune.0);
+        // removed along with `Enemy` when it dies, the removal may land this frame
+        let Ok(faction) = factions.get(entity) else {
+            continue;
+        };
+        let is_foe = |other: Entity| {
+            other != entity
+                && !dying.contains(&other)
+                && factions
+                    .get(other)
+                    .is_ok_and(|other| hostility.hostile(faction, other))
+        };
         let transform = transforms.get(entity).unwrap();
         let pos_3d = transform.translation;
         let pos = pos_3d.xz();
+        let home = *enemy.home.get_or_insert(pos);
+        for cooldown in &mut enemy.cooldowns {
+            *cooldown -= delta;
+        }
 
         let mut physics = all_physics.get_mut(entity).unwrap();
         physics.move_vec = Vec2::ZERO;
         physics.speed = enemy.speed;
         physics.ignore_overlap = false;
+        let eye = pos_3d + Vec3::Y * physics.hitbox.max.y;
+        let facing = -physics.look_to.as_vec2();
         drop(physics);
 
+        // calm enemies turn on the nearest foe they see
+        let calm = matches!(
+            enemy.state,
+            State::Idle { .. } | State::Patrol { .. } | State::Search { .. }
+        );
+        let foe = calm
+            .then(|| {
+                level
+                    .creatures_within(pos_3d, perception.view_distance, None)
+                    .into_iter()
+                    .filter(|(other, _)| is_foe(*other))
+                    .filter_map(|(other, _)| Some((other, transforms.get(other).ok()?.translation)))
+                    .find(|(_, other_3d)| {
+                        perception.sees(&level, eye, facing, *other_3d + Vec3::Y * EYE_HEIGHT)
+                    })
+                    .map(|(other, other_3d)| (other, other_3d.xz()))
+            })
+            .flatten();
+        if let Some((foe, foe_pos)) = foe {
+            enemy.state = aggro(&mut commands, entity, foe, foe_pos);
+        }
+
         match enemy.state.clone() {
-            State::Idle => {
-                if player_pos.distance(pos) < default_aggro_distance {
-                    enemy.state = State::Walk {
-                        aggro_timer: default_aggro_timer,
-                        aggro_entity: *player,
+            State::Idle { wait } => {
+                if wait <= 0.0 && enemy.speed > 0.0 {
+                    enemy.state = match patrol_node(&level, pos, home, patrol_radius, &mut rng.0) {
+                        Some(target) => State::Patrol { target },
+                        None => idle(&mut rng.0),
                     };
                 } else {
+                    enemy.state = State::Idle { wait: wait - delta };
                     enemy.animation = Some(Animation::Idle);
                 }
             }
+            State::Patrol { target } => {
+                let target_pos = level.graph[target];
+                if pos.distance(target_pos) < waypoint_reached {
+                    // stop for a while now and then, otherwise walk on
+                    enemy.state = match rng.0.random_bool(0.5) {
+                        true => idle(&mut rng.0),
+                        false => patrol_node(&level, pos, home, patrol_radius, &mut rng.0)
+                            .map_or_else(|| idle(&mut rng.0), |target| State::Patrol { target }),
+                    };
+                    continue;
+                }
+
+                let mut physics = all_physics.get_mut(entity).unwrap();
+                physics.speed = enemy.speed * patrol_speed;
+                steer(&level, &mut physics, pos, [target_pos], immune, delta);
+                if physics.move_vec == Vec2::ZERO {
+                    enemy.state = idle(&mut rng.0);
+                } else {
+                    enemy.animation = Some(Animation::Patrol);
+                }
+            }
             State::Walk {
                 mut aggro_timer,
                 aggro_entity,
+                mut last_seen,
+                mut flank,
             } => {
+                // killed, gone or no longer a foe
+                let Some(aggro_3d) = transforms
+                    .get(aggro_entity)
+                    .ok()
+                    .filter(|_| is_foe(aggro_entity))
+                    .map(|transform| transform.translation)
+                else {
+                    enemy.state = idle(&mut rng.0);
+                    continue;
+                };
+
                 if aggro_timer <= 0.0 {
-                    enemy.state = State::Idle;
+                    enemy.state = State::Search {
+                        path: level.find_path(pos, last_seen, immune),
+                        timer: search_timer,
+                    };
+                    continue;
+                }
+
+                if !enemy.fled && enemy.hp < enemy.flee_below * enemy.max_hp {
+                    enemy.fled = true;
+                    enemy.state = State::Flee {
+                        timer: flee_timer,
+                        threat: aggro_entity,
+                    };
                     continue;
                 }
 
                 let mut physics = all_physics.get_mut(entity).unwrap();
 
-                let aggro_pos = transforms.get(aggro_entity).unwrap().translation.xz();
+                let aggro_pos = aggro_3d.xz();
                 let aggro_pos_reachable = if -level.height(aggro_pos) < physics.radius {
                     aggro_pos + level.normal_2d(aggro_pos) * physics.radius
                 } else {
@@ -275,43 +615,73 @@ fn ai(
                 };
                 let aggro_dist = pos.distance(aggro_pos);
 
-                if aggro_dist > default_aggro_distance {
-                    aggro_timer -= time.delta_secs();
+                if !perception.sees(&level, eye, facing, aggro_3d + Vec3::Y * EYE_HEIGHT) {
+                    aggro_timer -= delta;
                 } else {
-                    aggro_timer = default_aggro_timer;
+                    aggro_timer = AGGRO_TIMER;
+                    last_seen = aggro_pos;
                 }
 
                 let nearest_node = level.nearest_id_terrain(1, pos)[0];
-                let aggro_nearest_node = level.nearest_id_terrain(1, aggro_pos)[0];
-
-                let (_, walk_path) = astar(
-                    &level.graph,
-                    nearest_node,
-                    |id| id == aggro_nearest_node,
-                    |e| *e.weight(),
-                    |_| 0.0,
-                )
-                .unwrap();
-
-                for target in walk_path
+                level.update_flow_field(aggro_entity, aggro_pos, immune);
+                let flow_field = level.flow_field(aggro_entity, immune).unwrap();
+
+                let walk_path = flow_field
+                    .path(nearest_node)
+                    .take(waypoint_lookahead)
+                    .collect::<Vec<_>>();
+                let reaches_target = walk_path.last() == Some(&flow_field.target());
+
+                let points = walk_path
                     .into_iter()
-                    .map(|node| *level.graph.node_weight(node).unwrap())
-                    .chain([aggro_pos_reachable])
+                    .map(|node| level.graph[node])
+                    .chain(reaches_target.then_some(aggro_pos_reachable))
+                    .collect::<Vec<_>>();
+
+                // pack members first go round to their side of the target, through the
+                // node nearest to it, and attack like the others once there or closer
+                let flank_dist = FLANK_SHARE * enemy.max_range();
+                let flank_point = flank.map(|flank| {
+                    let side = aggro_pos + flank * flank_dist;
+                    level.graph[level.nearest_id_terrain(1, side)[0]]
+                });
+                if aggro_dist < flank_dist
+                    || flank_point.is_some_and(|point| pos.distance(point) < waypoint_reached)
                 {
-                    if level.can_walk(pos, target, physics.radius - 0.001) {
-                        physics.move_vec = target - pos;
-                    }
+                    flank = None;
                 }
+                let flanking = flank_point
+                    .filter(|point| flank.is_some() && level.can_walk(pos, *point, physics.radius));
 
-                physics.look_to = physics.look_to.slerp(
-                    Dir2::new(-physics.move_vec).unwrap_or(Dir2::NEG_Y),
-                    time.delta_secs() * 10.0,
-                );
+                match flanking {
+                    Some(point) => steer(&level, &mut physics, pos, [point], immune, delta),
+                    None => steer(&level, &mut physics, pos, points, immune, delta),
+                }
 
-                if aggro_dist <= enemy.attack_range
-                    && level.can_walk(pos, aggro_pos_reachable, physics.radius)
-                {
+                let reachable =
+                    flanking.is_none() && level.can_walk(pos, aggro_pos_reachable, physics.radius);
+                let slot_free = melee_slots.get(&aggro_entity).copied().unwrap_or(0) < MELEE_SLOTS;
+                let attack = reachable
+                    .then(|| enemy.usable_attacks(aggro_dist, slot_free))
+                    .and_then(|usable| {
+                        let attacks = &enemy.attacks;
+                        usable
+                            .choose_weighted(&mut rng.0, |idx| attacks[*idx].weight)
+                            .ok()
+                            .copied()
+                    });
+                // a melee attack could be used if another attacker made room
+                let in_range = reachable
+                    && attack.is_none()
+                    && !enemy.usable_attacks(aggro_dist, true).is_empty();
+
+                if let Some(attack) = attack {
+                    if enemy.attacks[attack].is_melee() {
+                        *melee_slots.entry(aggro_entity).or_default() += 1;
+                    }
+                    enemy.cooldowns[attack] = enemy.attacks[attack].cooldown;
                     enemy.state = State::Attack {
+                        attack,
                         timer_prepare: 0.5,
                         timer_action: 0.5,
                         origin: pos,
@@ -319,16 +689,129 @@ fn ai(
                         target_pos: aggro_pos,
                         damage_done: false,
                     };
-                    enemy.animation = Some(Animation::Attack);
+                    enemy.animation = Some(Animation::Attack(attack));
                 } else {
+                    if in_range {
+                        // waits its turn facing the target
+                        physics.move_vec = Vec2::ZERO;
+                        physics.look_to = Dir2::new(pos - aggro_pos).unwrap_or(Dir2::NEG_Y);
+                    }
                     enemy.state = State::Walk {
                         aggro_timer,
                         aggro_entity,
+                        last_seen,
+                        flank,
                     };
+                    enemy.animation = Some(if in_range {
+                        Animation::Idle
+                    } else {
+                        Animation::Walk
+                    });
+                }
+            }
+            State::Search { mut path, timer } => {
+                if timer <= 0.0 {
+                    enemy.state = idle(&mut rng.0);
+                    continue;
+                }
+
+                let nearest_node = level.nearest_id_terrain(1, pos)[0];
+                if let Some(idx) = path.iter().position(|node| *node == nearest_node) {
+                    path.drain(..idx);
+                }
+                if let [last] = path[..]
+                    && level.graph[last].distance(pos) < waypoint_reached
+                {
+                    path.clear();
+                }
+
+                let mut physics = all_physics.get_mut(entity).unwrap();
+                if path.is_empty() {
+                    physics.look_to = Rot2::radians(delta * 2.0) * physics.look_to;
+                    enemy.animation = Some(Animation::Search);
+                } else {
+                    let points = path
+                        .iter()
+                        .take(waypoint_lookahead)
+                        .map(|node| level.graph[*node]);
+                    steer(&level, &mut physics, pos, points, immune, delta);
+                    if physics.move_vec == Vec2::ZERO {
+                        // stuck, look around from here
+                        path.clear();
+                    }
                     enemy.animation = Some(Animation::Walk);
                 }
+
+                enemy.state = State::Search {
+                    path,
+                    timer: timer - delta,
+                };
+            }
+            State::Flee { timer, threat } => {
+                let Ok(threat_pos) = transforms.get(threat).map(|t| t.translation.xz()) else {
+                    enemy.state = idle(&mut rng.0);
+                    continue;
+                };
+                if timer <= 0.0 {
+                    enemy.state = idle(&mut rng.0);
+                    continue;
+                }
+
+                // the node furthest from the threat around the one the enemy stands at
+                let node = level.nearest_id_terrain(1, pos)[0];
+                let refuge = level
+                    .graph
+                    .neighbors(node)
+                    .chain([node])
+                    .max_by(|a, b| {
+                        let (a, b) = (level.graph[*a], level.graph[*b]);
+                        a.distance(threat_pos).total_cmp(&b.distance(threat_pos))
+                    })
+                    .unwrap();
+
+                if refuge == node && level.graph[node].distance(pos) < waypoint_reached {
+                    // cornered, fight back
+                    enemy.state = aggro(&mut commands, entity, threat, threat_pos);
+                    continue;
+                }
+
+                let mut physics = all_physics.get_mut(entity).unwrap();
+                physics.speed = enemy.speed * flee_speed;
+                steer(
+                    &level,
+                    &mut physics,
+                    pos,
+                    [level.graph[refuge]],
+                    immune,
+                    delta,
+                );
+                enemy.state = State::Flee {
+                    timer: timer - delta,
+                    threat,
+                };
+                enemy.animation = Some(Animation::Flee);
+            }
+            State::Stagger { timer, attacker } => {
+                if timer <= 0.0 {
+                    // turns on whoever hit it, wherever they are
+                    enemy.state = if let Some(attacker) = attacker.filter(|other| is_foe(*other))
+                        && let Ok(attacker_transform) = transforms.get(attacker)
+                    {
+                        let attacker_pos = attacker_transform.translation.xz();
+                        aggro(&mut commands, entity, attacker, attacker_pos)
+                    } else {
+                        idle(&mut rng.0)
+                    };
+                } else {
+                    enemy.state = State::Stagger {
+                        timer: timer - delta,
+                        attacker,
+                    };
+                    enemy.animation = Some(Animation::Stagger);
+                }
             }
             State::Attack {
+                attack,
                 mut timer_prepare,
                 mut timer_action,
                 origin,
@@ -336,23 +819,37 @@ fn ai(
                 mut target_pos,
                 mut damage_done,
             } => {
-                let target_physics = all_physics.get(target).unwrap().clone();
+                // killed, gone or no longer a foe
+                let Some(target_physics) = all_physics
+                    .get(target)
+                    .ok()
+                    .filter(|_| is_foe(target))
+                    .cloned()
+                else {
+                    enemy.state = idle(&mut rng.0);
+                    continue;
+                };
                 let mut physics = all_physics.get_mut(entity).unwrap();
 
                 let diff = target_pos - origin;
                 physics.look_to = Dir2::new(-diff).unwrap_or(Dir2::NEG_Y);
 
                 if timer_prepare > 0.0 {
-                    timer_prepare -= time.delta_secs() / enemy.attack_delay;
+                    timer_prepare -= time.delta_secs() / enemy.attacks[attack].delay;
                     target_pos =
                         transforms.get(target).unwrap().translation.xz() - physics.look_to * 5.0;
                 } else if timer_action >= 0.0 {
-                    timer_action -= time.delta_secs() / enemy.attack_delay;
-                    match enemy.attack {
+                    let Attack {
+                        kind,
+                        max_range,
+                        delay,
+                        ..
+                    } = enemy.attacks[attack];
+                    timer_action -= time.delta_secs() / delay;
+                    match kind {
                         AttackKind::Melee(damage) => {
                             physics.move_vec = diff;
-                            physics.speed =
-                                2.0 * diff.length().min(enemy.attack_range) / enemy.attack_delay;
+                            physics.speed = 2.0 * diff.length().min(max_range) / delay;
                             physics.ignore_overlap = true;
                             if !damage_done {
                                 let inverse = global_transforms
@@ -426,7 +923,7 @@ fn ai(
                                     aabb_segment_intersection(physics.hitbox, segment)
                                 }) {
                                     damage_done = true;
-                                    commands.entity(target).insert(ApplyDamage(damage));
+                                    apply_damage(&mut commands, target, damage, Some(entity));
                                 }
                             }
                         }
@@ -436,21 +933,35 @@ fn ai(
                                 .get(enemy.scene)
                                 .unwrap()
                                 .transform_point(enemy.shoot_point);
+                            let target_center = global_transforms
+                                .get(target)
+                                .unwrap()
+                                .transform_point(target_physics.hitbox.center().into());
+                            let dir = aim::aim(
+                                shoot_point,
+                                target_center,
+                                target_physics.velocity().extend(0.0).xzy(),
+                                projectile,
+                                enemy.accuracy,
+                                &mut rng.0,
+                            );
                             projectile.spawn(
                                 &mut commands,
-                                Transform::from_translation(shoot_point)
-                                    .looking_at(target_pos.extend(1.7).xzy(), Vec3::Y),
-                                Damage::Player,
+                                Transform::from_translation(shoot_point).looking_to(dir, Vec3::Y),
+                                faction.clone(),
+                                entity,
                             );
                         }
                         _ => {}
                     }
                 } else {
-                    enemy.state = State::Idle;
+                    let target_pos = transforms.get(target).unwrap().translation.xz();
+                    enemy.state = aggro(&mut commands, entity, target, target_pos);
                     continue;
                 }
 
                 enemy.state = State::Attack {
+                    attack,
                     timer_prepare,
                     timer_action,
                     origin,
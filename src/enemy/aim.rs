@@ -0,0 +1,127 @@
+use std::f32::consts::TAU;
+
+use bevy::prelude::*;
+use rand::Rng;
+
+use crate::projectile::SpawnProjectile;
+
+// How far off the least accurate enemies shoot at most, in radians.
+const MAX_AIM_ERROR: f32 = 0.15;
+const SOLVER_STEPS: usize = 16;
+
+// Direction to launch `projectile` in from `from` to meet a target at `target` moving with
+// `target_vel`. Enemies lead the target by their share of `accuracy` and miss by the rest.
+pub fn aim(
+    from: Vec3,
+    target: Vec3,
+    target_vel: Vec3,
+    projectile: SpawnProjectile,
+    accuracy: f32,
+    rng: &mut impl Rng,
+) -> Dir3 {
+    let accuracy = accuracy.clamp(0.0, 1.0);
+    let (speed, aceleration) = projectile.ballistics();
+
+    let dir = solve(from, target, target_vel * accuracy, speed, aceleration).unwrap_or_else(|| {
+        // out of reach, throw as far as it flies toward the target
+        let flat = (target - from).with_y(0.0).normalize_or_zero();
+        Dir3::new(flat - aceleration.normalize_or_zero()).unwrap_or(Dir3::NEG_Z)
+    });
+
+    let error = (1.0 - accuracy) * MAX_AIM_ERROR;
+    if error <= 0.0 {
+        return dir;
+    }
+    let axis =
+        Quat::from_axis_angle(*dir, rng.random_range(0.0..TAU)) * dir.any_orthonormal_vector();
+    Quat::from_axis_angle(axis, rng.random_range(0.0..error)) * dir
+}
+
+// The projectile covers `speed * t` along its launch direction and drifts by its
+// acceleration on top, so the flight time is refined until the aim point it gives stops
+// moving. For arcs that is the low one, none if the target is out of reach.
+fn solve(
+    from: Vec3,
+    target: Vec3,
+    target_vel: Vec3,
+    speed: f32,
+    aceleration: Vec3,
+) -> Option<Dir3> {
+    if speed <= 0.0 {
+        return None;
+    }
+
+    let mut time = from.distance(target) / speed;
+    for _ in 0..SOLVER_STEPS {
+        let aim = target + target_vel * time - 0.5 * aceleration * time * time - from;
+        let next = aim.length() / speed;
+        if (next - time).abs() < 0.001 {
+            return Dir3::new(aim).ok();
+        }
+        time = next;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{SeedableRng, rngs::StdRng};
+
+    use super::*;
+
+    // Where a projectile launched along `dir` is once it has covered the distance to
+    // `target` horizontally.
+    fn landing(from: Vec3, dir: Dir3, target: Vec3, speed: f32, aceleration: Vec3) -> Vec3 {
+        let horizontal = (dir * speed).with_y(0.0).length();
+        let time = (target - from).with_y(0.0).length() / horizontal;
+        from + dir * speed * time + 0.5 * aceleration * time * time
+    }
+
+    #[test]
+    fn hits_a_stationary_target() {
+        let from = Vec3::new(0.0, 2.0, 0.0);
+        let target = Vec3::new(12.0, 0.5, -30.0);
+        let aceleration = Vec3::new(0.0, -9.8, 0.0);
+
+        for speed in [20.0, 60.0] {
+            let dir = solve(from, target, Vec3::ZERO, speed, aceleration).unwrap();
+            assert!(landing(from, dir, target, speed, aceleration).distance(target) < 0.05);
+        }
+    }
+
+    #[test]
+    fn shoots_straight_without_drift() {
+        let from = Vec3::ZERO;
+        let target = Vec3::new(3.0, 4.0, 12.0);
+        let dir = solve(from, target, Vec3::ZERO, 50.0, Vec3::ZERO).unwrap();
+        assert!(dir.angle_between(target - from) < 1e-4);
+    }
+
+    #[test]
+    fn out_of_reach() {
+        let target = Vec3::new(0.0, 0.0, -500.0);
+        let aceleration = Vec3::new(0.0, -9.8, 0.0);
+        assert!(solve(Vec3::ZERO, target, Vec3::ZERO, 10.0, aceleration).is_none());
+    }
+
+    #[test]
+    fn accurate_enemies_do_not_scatter() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let from = Vec3::ZERO;
+        let target = Vec3::new(0.0, 0.0, -20.0);
+        let (speed, aceleration) = SpawnProjectile::TreeProj.ballistics();
+
+        let dir = aim(
+            from,
+            target,
+            Vec3::ZERO,
+            SpawnProjectile::TreeProj,
+            1.0,
+            &mut rng,
+        );
+        assert_eq!(
+            Some(dir),
+            solve(from, target, Vec3::ZERO, speed, aceleration)
+        );
+    }
+}
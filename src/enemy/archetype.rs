@@ -0,0 +1,301 @@
+use std::{fmt, io};
+
+use bevy::{
+    asset::{AssetLoader, LoadContext, io::Reader},
+    prelude::*,
+};
+use gltf::Gltf;
+use rand::{
+    Rng,
+    distr::{Distribution, weighted::WeightedIndex},
+};
+use ron::error::SpannedError;
+use serde::Deserialize;
+use twg_2025_1::level::{BiomePixel, LevelBiome, hazards::HazardKind};
+
+use crate::{
+    enemy::{Attack, EnemyStats, perception::Perception},
+    faction::{Faction, Hostility, HostilityTable},
+    hazard::HazardImmune,
+    model_loader::{LoadModel, ReadyAction},
+};
+
+pub const TABLE_PATH: &str = "enemies.ron";
+
+#[derive(Deserialize)]
+pub struct EnemyArchetype {
+    pub name: String,
+    // File in `models/`, the name if not set.
+    #[serde(default)]
+    pub model: Option<String>,
+    pub scale: f32,
+    pub hp: f32,
+    pub speed: f32,
+    // Picked by distance to the target, weight and cooldown.
+    pub attacks: Vec<Attack>,
+    // Share of the hp below which the enemy runs away once.
+    #[serde(default)]
+    pub flee_below: f32,
+    // 1.0 leads moving targets and hits, lower misses more.
+    #[serde(default = "EnemyArchetype::default_accuracy")]
+    pub accuracy: f32,
+    #[serde(default)]
+    pub perception: Perception,
+    #[serde(default)]
+    pub immune: Vec<HazardKind>,
+    // Name in the hostility table, decides whom the enemy fights.
+    pub faction: String,
+    // Spawn weight in each biome, relative to the other archetypes there.
+    #[serde(default)]
+    pub spawn: Vec<(LevelBiome, f32)>,
+}
+
+impl EnemyArchetype {
+    pub fn model(&self) -> &str {
+        self.model.as_deref().unwrap_or(&self.name)
+    }
+
+    pub fn stats(&self) -> EnemyStats {
+        EnemyStats {
+            attacks: self.attacks.clone(),
+            speed: self.speed,
+            hp: self.hp,
+            flee_below: self.flee_below,
+            accuracy: self.accuracy,
+        }
+    }
+
+    fn default_accuracy() -> f32 {
+        1.0
+    }
+
+    // Weight at a point mixing several biomes, zero where it doesn't spawn.
+    fn spawn_weight(&self, biome: &BiomePixel) -> f32 {
+        self.spawn
+            .iter()
+            .map(|(kind, weight)| biome.weight(*kind) * weight.max(0.0))
+            .sum()
+    }
+}
+
+#[derive(Asset, TypePath, Deserialize)]
+pub struct EnemyTable {
+    enemies: Vec<EnemyArchetype>,
+}
+
+impl EnemyTable {
+    pub fn get(&self, name: &str) -> Option<&EnemyArchetype> {
+        self.enemies.iter().find(|enemy| enemy.name == name)
+    }
+
+    pub fn choose(&self, biome: &BiomePixel, rng: &mut impl Rng) -> Option<&EnemyArchetype> {
+        let weights = self.enemies.iter().map(|enemy| enemy.spawn_weight(biome));
+        let dist = WeightedIndex::new(weights).ok()?;
+        Some(&self.enemies[dist.sample(rng)])
+    }
+}
+
+#[derive(Debug)]
+pub enum EnemyTableError {
+    Io(io::Error),
+    Parse(SpannedError),
+    DuplicateEnemy(String),
+    NoAttacks(String),
+    Model {
+        enemy: String,
+        error: String,
+    },
+    MissingClip {
+        enemy: String,
+        clip: String,
+    },
+    MissingAttackClip {
+        enemy: String,
+        attack: usize,
+        clip: String,
+    },
+    MissingShootPoint(String),
+}
+
+impl fmt::Display for EnemyTableError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "{err}"),
+            Self::Parse(err) => write!(f, "{err}"),
+            Self::DuplicateEnemy(name) => write!(f, "enemy \"{name}\" is defined twice"),
+            Self::NoAttacks(name) => write!(f, "enemy \"{name}\" has no attacks"),
+            Self::Model { enemy, error } => {
+                write!(f, "model of enemy \"{enemy}\" can't be read: {error}")
+            }
+            Self::MissingClip { enemy, clip } => {
+                write!(f, "model of enemy \"{enemy}\" has no \"{clip}\" animation")
+            }
+            Self::MissingAttackClip {
+                enemy,
+                attack,
+                clip,
+            } => write!(
+                f,
+                "attacks[{attack}] of enemy \"{enemy}\" plays \"{clip}\", which its model doesn't have"
+            ),
+            Self::MissingShootPoint(name) => {
+                write!(f, "ranged enemy \"{name}\" has no shoot point in its model")
+            }
+        }
+    }
+}
+
+impl std::error::Error for EnemyTableError {}
+
+#[derive(Default)]
+pub struct EnemyTableLoader;
+
+impl AssetLoader for EnemyTableLoader {
+    type Asset = EnemyTable;
+    type Settings = ();
+    type Error = EnemyTableError;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &(),
+        load_context: &mut LoadContext<'_>,
+    ) -> Result<EnemyTable, EnemyTableError> {
+        let mut bytes = vec![];
+        reader
+            .read_to_end(&mut bytes)
+            .await
+            .map_err(EnemyTableError::Io)?;
+        let table = ron::de::from_bytes::<EnemyTable>(&bytes).map_err(EnemyTableError::Parse)?;
+
+        for (idx, enemy) in table.enemies.iter().enumerate() {
+            if table.enemies[..idx].iter().any(|e| e.name == enemy.name) {
+                return Err(EnemyTableError::DuplicateEnemy(enemy.name.clone()));
+            }
+            if enemy.attacks.is_empty() {
+                return Err(EnemyTableError::NoAttacks(enemy.name.clone()));
+            }
+            check_model(enemy, load_context).await?;
+        }
+        Ok(table)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["enemies.ron"]
+    }
+}
+
+// The clips and shoot point the enemy needs, looked up in the glTF json without loading
+// the model, so a typo in the table fails its load instead of the spawn.
+async fn check_model(
+    enemy: &EnemyArchetype,
+    load_context: &mut LoadContext<'_>,
+) -> Result<(), EnemyTableError> {
+    let model_error = |error: String| EnemyTableError::Model {
+        enemy: enemy.name.clone(),
+        error,
+    };
+    let bytes = load_context
+        .read_asset_bytes(format!("models/{}.glb", enemy.model()))
+        .await
+        .map_err(|err| model_error(err.to_string()))?;
+    let gltf = Gltf::from_slice(&bytes).map_err(|err| model_error(err.to_string()))?;
+
+    let has_clip = |clip: &str| gltf.animations().any(|anim| anim.name() == Some(clip));
+    if let Some(clip) = ["idle", "walk", "attack", "death"]
+        .into_iter()
+        .find(|clip| !has_clip(clip))
+    {
+        return Err(EnemyTableError::MissingClip {
+            enemy: enemy.name.clone(),
+            clip: clip.to_string(),
+        });
+    }
+    if let Some((attack, clip)) = enemy
+        .attacks
+        .iter()
+        .map(|attack| &attack.animation)
+        .enumerate()
+        .find(|(_, clip)| !has_clip(clip))
+    {
+        return Err(EnemyTableError::MissingAttackClip {
+            enemy: enemy.name.clone(),
+            attack,
+            clip: clip.clone(),
+        });
+    }
+
+    // enemies that also bite spit from the top of the hitbox without one
+    let ranged = enemy.attacks.iter().any(|attack| !attack.is_melee());
+    let melee = enemy.attacks.iter().any(|attack| attack.is_melee());
+    let has_shoot_point = gltf.nodes().any(|node| node.name() == Some("shoot_point"));
+    if ranged && !melee && !has_shoot_point {
+        return Err(EnemyTableError::MissingShootPoint(enemy.name.clone()));
+    }
+    Ok(())
+}
+
+#[derive(Resource)]
+pub struct EnemyArchetypes(pub Handle<EnemyTable>);
+
+impl EnemyArchetypes {
+    pub fn table<'a>(&self, tables: &'a Assets<EnemyTable>) -> Option<&'a EnemyTable> {
+        tables.get(&self.0)
+    }
+}
+
+// Name of an archetype in the enemy table, resolved into the model and stats once
+// the table is loaded.
+#[derive(Component)]
+pub struct EnemyKind(pub String);
+
+pub fn init(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(EnemyArchetypes(asset_server.load(TABLE_PATH)));
+}
+
+pub fn loaded(archetypes: Res<EnemyArchetypes>, tables: Res<Assets<EnemyTable>>) -> bool {
+    archetypes.table(&tables).is_some()
+}
+
+// Runs only once the enemy and faction tables are loaded, so kinds spawned before that
+// are still `Added`.
+pub fn resolve(
+    mut commands: Commands,
+    archetypes: Res<EnemyArchetypes>,
+    tables: Res<Assets<EnemyTable>>,
+    hostility: Res<HostilityTable>,
+    hostility_tables: Res<Assets<Hostility>>,
+    kinds: Query<(Entity, &EnemyKind), Added<EnemyKind>>,
+) {
+    let Some(table) = archetypes.table(&tables) else {
+        return;
+    };
+    let Some(hostility) = hostility.get(&hostility_tables) else {
+        return;
+    };
+
+    for (entity, kind) in kinds {
+        let Some(enemy) = table.get(&kind.0) else {
+            error!("Unknown enemy {}", kind.0);
+            commands.entity(entity).despawn();
+            continue;
+        };
+        let faction = Faction::new(&enemy.faction);
+        if !hostility.contains(&faction) {
+            error!("Enemy {} has unknown faction {}", enemy.name, enemy.faction);
+            commands.entity(entity).despawn();
+            continue;
+        }
+
+        commands.entity(entity).insert((
+            LoadModel::new(
+                enemy.model(),
+                ReadyAction::Enemy(enemy.stats()),
+                Vec3::splat(enemy.scale),
+            ),
+            enemy.perception,
+            HazardImmune(enemy.immune.clone()),
+            faction,
+        ));
+    }
+}
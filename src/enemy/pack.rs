@@ -0,0 +1,74 @@
+use std::f32::consts::TAU;
+
+use bevy::prelude::*;
+
+use crate::{
+    enemy::{AGGRO_TIMER, Enemy, State, archetype::EnemyKind},
+    faction::Faction,
+    level::{Level, creatures::CreatureKind},
+};
+
+// How many melee enemies attack the same target at once, the others wait their turn.
+pub const MELEE_SLOTS: usize = 2;
+// Pack members take their side of the target at this share of their attack range.
+pub const FLANK_SHARE: f32 = 0.75;
+
+const ALERT_RADIUS: f32 = 20.0;
+// At most this many neighbours join in, the alerting enemy included.
+const PACK_SIZE: usize = 6;
+const FLANK_SPREAD: f32 = TAU / 8.0;
+
+// Sent when `enemy` turns on `target`, calm enemies of the same kind and faction around it
+// join the chase.
+#[derive(Event)]
+pub struct PackAlert {
+    pub enemy: Entity,
+    pub target: Entity,
+    pub last_seen: Vec2,
+}
+
+pub fn alert(
+    level: Res<Level>,
+    mut alerts: EventReader<PackAlert>,
+    mut enemies: Query<(&mut Enemy, &EnemyKind, &Faction, &Transform)>,
+) {
+    for alert in alerts.read() {
+        let Ok((_, kind, faction, transform)) = enemies.get(alert.enemy) else {
+            continue;
+        };
+        let kind = kind.0.clone();
+        let faction = faction.clone();
+        let pos = transform.translation;
+        let base = (pos.xz() - alert.last_seen).to_angle();
+
+        let mut side = 0_usize;
+        for (member, dist_squared) in
+            level.nearest_creatures(PACK_SIZE, pos, Some(CreatureKind::Enemy))
+        {
+            if member == alert.enemy || dist_squared > ALERT_RADIUS * ALERT_RADIUS {
+                continue;
+            }
+            let Ok((mut enemy, member_kind, member_faction, _)) = enemies.get_mut(member) else {
+                continue;
+            };
+            let calm = matches!(
+                enemy.state,
+                State::Idle { .. } | State::Patrol { .. } | State::Search { .. }
+            );
+            if !calm || member_kind.0 != kind || *member_faction != faction {
+                continue;
+            }
+
+            // members alternate sides, each pair further around the target
+            side += 1;
+            let sign = if side % 2 == 1 { 1.0 } else { -1.0 };
+            let angle = base + sign * FLANK_SPREAD * side.div_ceil(2) as f32;
+            enemy.state = State::Walk {
+                aggro_timer: AGGRO_TIMER,
+                aggro_entity: alert.target,
+                last_seen: alert.last_seen,
+                flank: Some(Vec2::from_angle(angle)),
+            };
+        }
+    }
+}
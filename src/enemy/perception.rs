@@ -0,0 +1,88 @@
+use bevy::prelude::*;
+use serde::Deserialize;
+
+use crate::{
+    enemy::{Enemy, State},
+    hazard::HazardImmune,
+    level::Level,
+};
+
+// Height of the player's eyes above its feet, where enemies look for it.
+pub const EYE_HEIGHT: f32 = 1.5;
+
+// How long an enemy looks around where it heard a noise.
+const INVESTIGATE_TIME: f32 = 8.0;
+
+#[derive(Component, Clone, Copy, Deserialize)]
+#[serde(default)]
+pub struct Perception {
+    pub view_distance: f32,
+    // Full width of the view cone in degrees, 360 to see all around.
+    pub view_angle: f32,
+    // Within this distance the target is noticed behind the enemy's back too.
+    pub awareness: f32,
+    // Multiplies the radius of every noise the enemy could hear.
+    pub hearing: f32,
+}
+
+impl Default for Perception {
+    fn default() -> Self {
+        Self {
+            view_distance: 25.0,
+            view_angle: 140.0,
+            awareness: 4.0,
+            hearing: 1.0,
+        }
+    }
+}
+
+impl Perception {
+    pub fn sees(&self, level: &Level, eye: Vec3, facing: Vec2, target: Vec3) -> bool {
+        let dist = eye.xz().distance(target.xz());
+        if dist > self.view_distance {
+            return false;
+        }
+        let in_cone = dist <= self.awareness
+            || facing.angle_to(target.xz() - eye.xz()).abs().to_degrees() <= 0.5 * self.view_angle;
+        in_cone && level.has_line_of_sight(eye, target)
+    }
+}
+
+// Something loud at `pos`, enemies that hear it come to look.
+#[derive(Event)]
+pub struct Noise {
+    pub pos: Vec2,
+    pub radius: f32,
+}
+
+// Only calm enemies are drawn by noises, chasing, fighting and fleeing ones carry on.
+pub fn hear(
+    level: Res<Level>,
+    mut noises: EventReader<Noise>,
+    mut enemies: Query<(&mut Enemy, &Perception, &Transform, Option<&HazardImmune>)>,
+) {
+    for noise in noises.read() {
+        for (mut enemy, perception, transform, immune) in &mut enemies {
+            let pos = transform.translation.xz();
+            let calm = matches!(
+                enemy.state,
+                State::Idle { .. } | State::Patrol { .. } | State::Search { .. }
+            );
+            if !calm || pos.distance(noise.pos) > noise.radius * perception.hearing {
+                continue;
+            }
+            // bursts of shots from the same spot don't restart the search
+            if let State::Search { path, .. } = &enemy.state
+                && path.last() == Some(&level.nearest_id_terrain(1, noise.pos)[0])
+            {
+                continue;
+            }
+
+            let immune = immune.map_or(&[][..], |immune| &immune.0);
+            enemy.state = State::Search {
+                path: level.find_path(pos, noise.pos, immune),
+                timer: INVESTIGATE_TIME,
+            };
+        }
+    }
+}
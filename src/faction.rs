@@ -0,0 +1,228 @@
+use std::{collections::HashMap, fmt, io};
+
+use bevy::{
+    asset::{AssetLoader, LoadContext, io::Reader},
+    prelude::*,
+};
+use ron::error::SpannedError;
+use serde::Deserialize;
+
+use crate::GameState;
+
+pub const TABLE_PATH: &str = "factions.ron";
+
+pub const PLAYER: &str = "player";
+pub const BOSS: &str = "boss";
+
+pub struct FactionPlugin;
+
+impl Plugin for FactionPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<Hostility>();
+        app.init_asset_loader::<HostilityLoader>();
+        app.add_systems(Startup, init);
+        app.add_systems(Update, start_game.run_if(loaded));
+    }
+}
+
+// Side a creature fights for, projectiles carry the faction of whoever shot them.
+// Charmed enemies fight for the player once they are given its faction.
+#[derive(Component, Clone, PartialEq, Eq, Debug, Deserialize)]
+pub struct Faction(pub String);
+
+impl Faction {
+    pub fn new(name: &str) -> Self {
+        Self(name.to_string())
+    }
+}
+
+#[derive(Deserialize)]
+struct FactionEntry {
+    name: String,
+    // Factions whose members this one attacks and hurts.
+    #[serde(default)]
+    hostile: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct FactionTable {
+    factions: Vec<FactionEntry>,
+}
+
+#[derive(Debug)]
+pub enum FactionError {
+    Io(io::Error),
+    Parse(SpannedError),
+    DuplicateFaction(String),
+    UnknownFaction { faction: String, hostile: String },
+    MissingFaction(&'static str),
+}
+
+impl fmt::Display for FactionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "{err}"),
+            Self::Parse(err) => write!(f, "{err}"),
+            Self::DuplicateFaction(name) => write!(f, "faction \"{name}\" is defined twice"),
+            Self::UnknownFaction { faction, hostile } => {
+                write!(
+                    f,
+                    "faction \"{faction}\" is hostile to undefined \"{hostile}\""
+                )
+            }
+            Self::MissingFaction(name) => write!(f, "faction \"{name}\" is not defined"),
+        }
+    }
+}
+
+impl std::error::Error for FactionError {}
+
+// Who attacks and hurts whom, one way: a faction may be hostile to another that leaves
+// it alone. Factions hostile to nobody and to whom nobody is hostile stay out of fights.
+#[derive(Asset, TypePath)]
+pub struct Hostility(HashMap<String, Vec<String>>);
+
+impl Hostility {
+    pub fn parse(bytes: &[u8]) -> Result<Self, FactionError> {
+        let table = ron::de::from_bytes::<FactionTable>(bytes).map_err(FactionError::Parse)?;
+
+        let mut hostility = HashMap::new();
+        for faction in &table.factions {
+            if hostility
+                .insert(faction.name.clone(), faction.hostile.clone())
+                .is_some()
+            {
+                return Err(FactionError::DuplicateFaction(faction.name.clone()));
+            }
+        }
+        for faction in &table.factions {
+            if let Some(hostile) = faction
+                .hostile
+                .iter()
+                .find(|hostile| !hostility.contains_key(*hostile))
+            {
+                return Err(FactionError::UnknownFaction {
+                    faction: faction.name.clone(),
+                    hostile: hostile.clone(),
+                });
+            }
+        }
+        if let Some(missing) = [PLAYER, BOSS]
+            .into_iter()
+            .find(|name| !hostility.contains_key(*name))
+        {
+            return Err(FactionError::MissingFaction(missing));
+        }
+        Ok(Self(hostility))
+    }
+
+    pub fn contains(&self, faction: &Faction) -> bool {
+        self.0.contains_key(&faction.0)
+    }
+
+    // Whether members of `from` attack members of `to` and their projectiles hurt them.
+    pub fn hostile(&self, from: &Faction, to: &Faction) -> bool {
+        self.0
+            .get(&from.0)
+            .is_some_and(|hostile| hostile.contains(&to.0))
+    }
+}
+
+#[derive(Default)]
+pub struct HostilityLoader;
+
+impl AssetLoader for HostilityLoader {
+    type Asset = Hostility;
+    type Settings = ();
+    type Error = FactionError;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &(),
+        _load_context: &mut LoadContext<'_>,
+    ) -> Result<Hostility, FactionError> {
+        let mut bytes = vec![];
+        reader
+            .read_to_end(&mut bytes)
+            .await
+            .map_err(FactionError::Io)?;
+        Hostility::parse(&bytes)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["factions.ron"]
+    }
+}
+
+#[derive(Resource)]
+pub struct HostilityTable(pub Handle<Hostility>);
+
+impl HostilityTable {
+    pub fn get<'a>(&self, tables: &'a Assets<Hostility>) -> Option<&'a Hostility> {
+        tables.get(&self.0)
+    }
+}
+
+fn init(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(HostilityTable(asset_server.load(TABLE_PATH)));
+}
+
+pub fn loaded(hostility: Res<HostilityTable>, tables: Res<Assets<Hostility>>) -> bool {
+    hostility.get(&tables).is_some()
+}
+
+// Projectiles and enemies can't tell friend from foe without the table, play starts
+// once it's loaded.
+fn start_game(mut game_state: ResMut<GameState>) {
+    if let GameState::Loading = *game_state {
+        *game_state = GameState::Running;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(factions: &str) -> Result<Hostility, FactionError> {
+        Hostility::parse(format!("(factions: [{factions}])").as_bytes())
+    }
+
+    #[test]
+    fn parses_the_table() {
+        let hostility = Hostility::parse(include_bytes!("../assets/factions.ron")).unwrap();
+        let [player, boss, forest, wildlife] =
+            ["player", "boss", "forest", "wildlife"].map(Faction::new);
+
+        assert!(hostility.hostile(&player, &boss));
+        assert!(hostility.hostile(&boss, &player));
+        assert!(hostility.hostile(&forest, &player));
+        assert!(!hostility.hostile(&wildlife, &player));
+        assert!(!hostility.hostile(&player, &wildlife));
+        assert!(!hostility.contains(&Faction::new("nobody")));
+    }
+
+    #[test]
+    fn one_way() {
+        let hostility = parse(r#"(name: "player", hostile: ["boss"]), (name: "boss")"#).unwrap();
+        assert!(hostility.hostile(&Faction::new(PLAYER), &Faction::new(BOSS)));
+        assert!(!hostility.hostile(&Faction::new(BOSS), &Faction::new(PLAYER)));
+    }
+
+    #[test]
+    fn rejects_bad_tables() {
+        assert!(matches!(
+            parse(r#"(name: "player"), (name: "boss"), (name: "player")"#),
+            Err(FactionError::DuplicateFaction(name)) if name == "player"
+        ));
+        assert!(matches!(
+            parse(r#"(name: "player", hostile: ["ghost"]), (name: "boss")"#),
+            Err(FactionError::UnknownFaction { faction, hostile })
+                if faction == "player" && hostile == "ghost"
+        ));
+        assert!(matches!(
+            parse(r#"(name: "player")"#),
+            Err(FactionError::MissingFaction(BOSS))
+        ));
+    }
+}
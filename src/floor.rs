@@ -0,0 +1,391 @@
+use std::{collections::HashSet, f32::consts::TAU};
+
+use bevy::prelude::*;
+use petgraph::visit::EdgeRef;
+use rand::{Rng, rngs::StdRng, seq::SliceRandom};
+
+use crate::{
+    DeferDespawn, GameRng,
+    boss::BossSpawner,
+    decoration::Decoration,
+    enemy::archetype::{EnemyArchetypes, EnemyKind, EnemyTable},
+    gate::{self, PartEnemy},
+    hazard::HazardZone,
+    heart::HeartSpawner,
+    level::{Level, LevelBiome, creatures::CreatureKind},
+    player::Player,
+    projectile::Projectile,
+    ui::UserNotify,
+    weapon::{biogun::Biogun, ion_cannon::IonCannon, pulse_rifle::PulseRifle, zapper::Zapper},
+};
+
+pub struct FloorPlugin;
+
+impl Plugin for FloorPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<EnterFloor>();
+        app.add_event::<FloorChanged>();
+        app.add_systems(Update, setup);
+        app.add_systems(Update, animate);
+        app.add_systems(Update, use_portals);
+        app.add_systems(Update, record_kills.before(clear_floor));
+        app.add_systems(Update, clear_floor.after(use_portals));
+        app.add_systems(Update, enter_floor.after(clear_floor));
+        app.add_systems(
+            Update,
+            spawn_enemies
+                .after(enter_floor)
+                .run_if(resource_exists::<PendingEnemies>),
+        );
+    }
+}
+
+// Levels of the floors the player isn't on, the active one is the `Level` resource.
+#[derive(Resource)]
+pub struct Floors {
+    current: usize,
+    stored: Vec<Option<Level>>,
+    // Parts of each floor whose pickup the player took, they aren't put back on a revisit.
+    taken: Vec<HashSet<usize>>,
+    // Enemies rolled for each floor on its first visit, by archetype name.
+    spawns: Vec<Option<Vec<(String, Vec2)>>>,
+    // Indices into `spawns` of the enemies killed on each floor, the rest come back.
+    killed: Vec<HashSet<usize>>,
+    // Explored areas of each visited floor, swapped into `DynamicLightmap` with the floor.
+    lightmaps: Vec<Option<Handle<Image>>>,
+}
+
+impl Floors {
+    // `levels` are the floors after the first one, which starts as the `Level` resource.
+    pub fn new(levels: Vec<Level>) -> Self {
+        let count = levels.len() + 1;
+        Self {
+            current: 0,
+            stored: [None]
+                .into_iter()
+                .chain(levels.into_iter().map(Some))
+                .collect(),
+            taken: vec![HashSet::new(); count],
+            spawns: vec![None; count],
+            killed: vec![HashSet::new(); count],
+            lightmaps: vec![None; count],
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.stored.len()
+    }
+
+    pub fn take_pickup(&mut self, pickup: &PartPickup) {
+        self.taken[self.current].insert(pickup.0);
+    }
+
+    pub fn lightmap(&self) -> Option<&Handle<Image>> {
+        self.lightmaps[self.current].as_ref()
+    }
+
+    pub fn set_lightmap(&mut self, lightmap: Handle<Image>) {
+        self.lightmaps[self.current] = Some(lightmap);
+    }
+
+    pub fn kill_enemy(&mut self, spawn: &EnemySpawn) {
+        self.killed[self.current].insert(spawn.0);
+    }
+}
+
+// Index of the part of the current floor `populate` put the heart, weapon or portal in,
+// hearts and weapons taken from there don't come back.
+#[derive(Component)]
+pub struct PartPickup(pub usize);
+
+// Index of the enemy in the current floor's spawn list, killed ones don't come back.
+#[derive(Component)]
+pub struct EnemySpawn(pub usize);
+
+// Despawned when the player leaves the floor.
+#[derive(Component)]
+pub struct FloorEntity;
+
+#[derive(Component)]
+pub struct Portal {
+    pub floor: usize,
+}
+
+#[derive(Event)]
+pub struct EnterFloor(usize);
+
+// Sent after the `Level` resource is swapped for another floor.
+#[derive(Event)]
+pub struct FloorChanged;
+
+// Where the player appears on the floor, at the top of the home part.
+pub fn home_point(level: &Level) -> Vec2 {
+    let home = level.parts()[0].bounds();
+    level.nearest_terrain(1, Vec2::new(home.center().x, home.max.y))[0].unwrap()
+}
+
+// A point a few metres from `point` along the road, so the player doesn't stand in a portal.
+pub fn step_from(level: &Level, point: Vec2) -> Vec2 {
+    let node = level.nearest_id_terrain(1, point)[0];
+    let next = level.graph.neighbors(node).next().unwrap_or(node);
+    point + (level.graph[next] - point).normalize_or_zero() * 5.0
+}
+
+// Portals down are put in the last part of the floor, the safe part before the boss
+// on the last floor.
+fn exit_point(level: &Level) -> Option<Vec2> {
+    let part = level.parts().last()?;
+    level.nearest_terrain(1, part.bounds().center())[0]
+}
+
+// Enemies wait for the enemy table, `spawn_enemies` places them once it's loaded.
+#[derive(Resource)]
+pub struct PendingEnemies;
+
+pub fn spawn_enemies(
+    mut commands: Commands,
+    level: Res<Level>,
+    mut floors: ResMut<Floors>,
+    mut rng: ResMut<GameRng>,
+    archetypes: Res<EnemyArchetypes>,
+    tables: Res<Assets<EnemyTable>>,
+) {
+    let Some(table) = archetypes.table(&tables) else {
+        return;
+    };
+    commands.remove_resource::<PendingEnemies>();
+    let rng = &mut rng.0;
+    let floors = &mut *floors;
+    let current = floors.current;
+
+    let spawns = floors.spawns[current].get_or_insert_with(|| {
+        let mut enemy_points = vec![];
+        for edge in level.graph.edge_references() {
+            let source = level.graph.node_weight(edge.source()).unwrap();
+            let target = level.graph.node_weight(edge.target()).unwrap();
+            let dir = (target - source).normalize();
+            let dist = source.distance(*target);
+            for _ in 0..10 {
+                enemy_points.push(source + dir * rng.random_range(0.0..=dist));
+            }
+        }
+
+        enemy_points.shuffle(rng);
+
+        let mut spawns = vec![];
+        while let Some(point) = enemy_points.pop() {
+            if spawns.len() >= 100 {
+                break;
+            }
+            if let Some(enemy) = table.choose(&level.biome(point), rng) {
+                spawns.push((enemy.name.clone(), point));
+            }
+        }
+        spawns
+    });
+
+    for (idx, (name, point)) in spawns.iter().enumerate() {
+        if floors.killed[current].contains(&idx) {
+            continue;
+        }
+        let mut enemy = commands.spawn((
+            EnemyKind(name.clone()),
+            EnemySpawn(idx),
+            FloorEntity,
+            Transform::from_xyz(point.x, 0.0, point.y),
+        ));
+        if let Some(part) = level.part_at(*point) {
+            enemy.insert(PartEnemy(part));
+        }
+    }
+}
+
+pub fn populate(commands: &mut Commands, level: &Level, rng: &mut StdRng, floors: &Floors) {
+    commands.insert_resource(PendingEnemies);
+
+    let exit_part = (floors.current + 1 < floors.count()).then(|| level.parts().len() - 1);
+
+    for (idx, part) in level.parts().iter().enumerate() {
+        if floors.taken[floors.current].contains(&idx) {
+            continue;
+        }
+        let pos = level.nearest_terrain(1, part.bounds().center())[0].unwrap();
+        let transform = Transform::from_translation(pos.extend(0.0).xzy());
+        let mut entity = match part.biome() {
+            _ if exit_part == Some(idx) => commands.spawn(Portal {
+                floor: floors.current + 1,
+            }),
+            LevelBiome::Home | LevelBiome::Safe => commands.spawn(HeartSpawner),
+            LevelBiome::Cave => commands.spawn(PulseRifle),
+            LevelBiome::Mushroom => commands.spawn(Zapper),
+            LevelBiome::Temple => commands.spawn(IonCannon),
+            LevelBiome::Meat => commands.spawn(Biogun),
+            LevelBiome::Boss => commands.spawn(BossSpawner),
+            LevelBiome::Forest => continue,
+        };
+        entity.insert((PartPickup(idx), FloorEntity, transform));
+    }
+
+    for prop in level.props() {
+        let pos = prop.pos.extend(level.height(prop.pos).max(0.0)).xzy();
+        commands.spawn((
+            Decoration(prop.kind),
+            FloorEntity,
+            Transform::from_translation(pos)
+                .with_rotation(Quat::from_rotation_y(prop.rotation))
+                .with_scale(Vec3::splat(prop.scale)),
+        ));
+    }
+
+    gate::populate(commands, level, rng);
+
+    for hazard in level.hazards() {
+        commands.spawn((
+            HazardZone(*hazard),
+            FloorEntity,
+            Transform::from_translation(hazard.pos.extend(0.0).xzy()),
+        ));
+    }
+
+    if floors.current > 0 {
+        let pos = home_point(level);
+        commands.spawn((
+            Portal {
+                floor: floors.current - 1,
+            },
+            FloorEntity,
+            Transform::from_translation(pos.extend(0.0).xzy()),
+        ));
+    }
+}
+
+fn setup(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    portals: Query<Entity, Added<Portal>>,
+) {
+    for portal in portals {
+        commands
+            .entity(portal)
+            .insert(Visibility::default())
+            .with_child((
+                Mesh3d(meshes.add(Torus::new(1.6, 2.0))),
+                MeshMaterial3d(materials.add(StandardMaterial {
+                    base_color: Color::BLACK,
+                    emissive: LinearRgba::rgb(4.0, 0.6, 6.0),
+                    ..Default::default()
+                })),
+                Transform::from_xyz(0.0, 2.5, 0.0).with_rotation(Quat::from_rotation_x(TAU / 4.0)),
+            ));
+    }
+}
+
+fn animate(
+    portals: Query<&Children, With<Portal>>,
+    mut transforms: Query<&mut Transform>,
+    time: Res<Time>,
+) {
+    for children in portals {
+        for child in children {
+            if let Ok(mut transform) = transforms.get_mut(*child) {
+                let angle = 0.25 * time.elapsed_secs() * TAU;
+                transform.rotation =
+                    Quat::from_rotation_y(angle) * Quat::from_rotation_x(TAU / 4.0);
+            }
+        }
+    }
+}
+
+fn use_portals(
+    player: Single<(&Player, &Transform)>,
+    portals: Query<(&Portal, &Transform)>,
+    mut enter_floor: EventWriter<EnterFloor>,
+    mut user_notify: EventWriter<UserNotify>,
+) {
+    let use_dist = 3.0;
+    let (player, player_transform) = player.into_inner();
+
+    for (portal, transform) in portals {
+        let can_use = transform
+            .translation
+            .xz()
+            .distance(player_transform.translation.xz())
+            <= use_dist;
+        if can_use {
+            user_notify.write(UserNotify(
+                "Нажмите 'E'".to_string(),
+                format!("Чтобы перейти на этаж {}", portal.floor + 1),
+            ));
+        }
+        if can_use && player.interaction {
+            enter_floor.write(EnterFloor(portal.floor));
+            return;
+        }
+    }
+}
+
+// Dying enemies get `DeferDespawn`, the ones still alive when the player leaves are
+// put back on the next visit.
+fn record_kills(enemies: Query<&EnemySpawn, Added<DeferDespawn>>, mut floors: ResMut<Floors>) {
+    for spawn in enemies {
+        floors.kill_enemy(spawn);
+    }
+}
+
+fn clear_floor(
+    mut commands: Commands,
+    mut events: EventReader<EnterFloor>,
+    floor_entities: Query<Entity, With<FloorEntity>>,
+    projectiles: Query<Entity, With<Projectile>>,
+    despawns: Query<Entity, With<DeferDespawn>>,
+) {
+    if events.read().count() == 0 {
+        return;
+    }
+    // projectile particles are both, `try_despawn` skips the repeats
+    for entity in floor_entities.iter().chain(&projectiles).chain(&despawns) {
+        commands.entity(entity).try_despawn();
+    }
+}
+
+pub fn enter_floor(
+    mut commands: Commands,
+    mut events: EventReader<EnterFloor>,
+    mut level: ResMut<Level>,
+    mut floors: ResMut<Floors>,
+    mut rng: ResMut<GameRng>,
+    mut floor_changed: EventWriter<FloorChanged>,
+    player: Single<(Entity, &mut Transform), With<Player>>,
+) {
+    let Some(&EnterFloor(to)) = events.read().last() else {
+        return;
+    };
+    let from = floors.current;
+    let Some(next) = floors.stored.get_mut(to).and_then(Option::take) else {
+        return;
+    };
+
+    let mut prev = std::mem::replace(&mut *level, next);
+    prev.clear_creatures();
+    floors.stored[from] = Some(prev);
+    floors.current = to;
+
+    // arrive next to the portal leading back to the floor the player came from
+    let arrival = if to > from {
+        home_point(&level)
+    } else {
+        exit_point(&level).unwrap_or_else(|| home_point(&level))
+    };
+    let arrival = step_from(&level, arrival);
+
+    let (player, mut transform) = player.into_inner();
+    transform.translation.x = arrival.x;
+    transform.translation.z = arrival.y;
+    level.insert_creature(player, CreatureKind::Player, transform.translation);
+
+    populate(&mut commands, &level, &mut rng.0, &floors);
+
+    info!("Entered floor {to}, level seed: {}", level.seed());
+    floor_changed.write(FloorChanged);
+}
@@ -0,0 +1,325 @@
+use std::f32::consts::TAU;
+
+use bevy::prelude::*;
+use petgraph::graph::NodeIndex;
+use rand::{Rng, rngs::StdRng};
+use twg_2025_1::level::{
+    Level,
+    gates::{Gate, GateCondition},
+};
+
+use crate::{
+    DeferDespawn,
+    floor::{self, FloorEntity, PendingEnemies},
+    player::Player,
+    terrain::TerrainCarved,
+    ui::UserNotify,
+};
+
+pub struct GatePlugin;
+
+impl Plugin for GatePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<OpenGates>();
+        app.add_systems(Startup, init);
+        app.add_systems(Update, setup);
+        app.add_systems(Update, animate);
+        app.add_systems(Update, pick_keys);
+        app.add_systems(Update, pull_levers);
+        app.add_systems(
+            Update,
+            check_cleared
+                .after(floor::spawn_enemies)
+                .run_if(not(resource_exists::<PendingEnemies>)),
+        );
+        app.add_systems(
+            Update,
+            open_gates
+                .after(pick_keys)
+                .after(pull_levers)
+                .after(check_cleared),
+        );
+    }
+}
+
+// Index into `Level::gates`, the wall is despawned once the gate opens.
+#[derive(Component)]
+pub struct GateBarrier(pub usize);
+
+// The part an enemy was spawned in, for gates that open when it's cleared.
+#[derive(Component)]
+pub struct PartEnemy(pub usize);
+
+#[derive(Component)]
+pub struct GateKey(pub usize);
+
+#[derive(Component)]
+pub struct GateLever(pub usize);
+
+#[derive(Component)]
+struct LeverHandle;
+
+#[derive(Event)]
+struct OpenGates(usize);
+
+#[derive(Resource)]
+struct GateAssets {
+    wall: Handle<Mesh>,
+    wall_material: Handle<StandardMaterial>,
+    key: Handle<Mesh>,
+    key_material: Handle<StandardMaterial>,
+    post: Handle<Mesh>,
+    handle: Handle<Mesh>,
+    lever_material: Handle<StandardMaterial>,
+}
+
+fn init(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    commands.insert_resource(GateAssets {
+        wall: meshes.add(Cuboid::new(1.0, 1.0, 1.0)),
+        wall_material: materials.add(StandardMaterial {
+            base_color: Color::srgb(0.2, 0.05, 0.05),
+            emissive: LinearRgba::rgb(3.0, 0.4, 0.2),
+            ..Default::default()
+        }),
+        key: meshes.add(Cuboid::new(0.5, 0.5, 0.5)),
+        key_material: materials.add(StandardMaterial {
+            base_color: Color::BLACK,
+            emissive: LinearRgba::rgb(4.0, 3.0, 0.5),
+            ..Default::default()
+        }),
+        post: meshes.add(Cylinder::new(0.15, 1.2)),
+        handle: meshes.add(Capsule3d::new(0.08, 0.8)),
+        lever_material: materials.add(StandardMaterial {
+            base_color: Color::srgb(0.4, 0.35, 0.3),
+            metallic: 0.8,
+            perceptual_roughness: 0.4,
+            ..Default::default()
+        }),
+    });
+}
+
+// Walls across the gated roads, and the keys and levers of the parts whose gates
+// are still closed. Called from `floor::populate`.
+pub fn populate(commands: &mut Commands, level: &Level, rng: &mut StdRng) {
+    let closed = level
+        .gates()
+        .iter()
+        .enumerate()
+        .filter(|(_, gate)| !gate.open)
+        .collect::<Vec<_>>();
+
+    for &(idx, gate) in &closed {
+        commands.spawn((
+            GateBarrier(idx),
+            FloorEntity,
+            Transform::from_translation(gate.pos.extend(0.0).xzy())
+                .looking_to(gate.dir.extend(0.0).xzy(), Vec3::Y),
+        ));
+    }
+
+    let mut parts = closed
+        .iter()
+        .map(|(_, gate)| (gate.part, gate.condition))
+        .collect::<Vec<_>>();
+    parts.sort_by_key(|(part, _)| *part);
+    parts.dedup();
+
+    for (part, condition) in parts {
+        let node = NodeIndex::new(rng.random_range(level.parts()[part].nodes()));
+        let transform = Transform::from_translation(level.graph[node].extend(0.0).xzy());
+        match condition {
+            GateCondition::Clear => {}
+            GateCondition::Key => {
+                commands.spawn((GateKey(part), FloorEntity, transform));
+            }
+            GateCondition::Lever => {
+                commands.spawn((GateLever(part), FloorEntity, transform));
+            }
+        }
+    }
+}
+
+fn setup(
+    mut commands: Commands,
+    assets: Res<GateAssets>,
+    level: Res<Level>,
+    barriers: Query<(Entity, &GateBarrier), Added<GateBarrier>>,
+    keys: Query<Entity, Added<GateKey>>,
+    levers: Query<Entity, Added<GateLever>>,
+) {
+    for (entity, barrier) in barriers {
+        let gate = level.gates()[barrier.0];
+        let size = Vec3::new(2.0 * gate.half_width, 4.0, 2.0 * Gate::THICKNESS);
+        commands
+            .entity(entity)
+            .insert(Visibility::default())
+            .with_child((
+                Mesh3d(assets.wall.clone()),
+                MeshMaterial3d(assets.wall_material.clone()),
+                Transform::from_xyz(0.0, 0.5 * size.y, 0.0).with_scale(size),
+            ));
+    }
+
+    for entity in keys {
+        commands
+            .entity(entity)
+            .insert(Visibility::default())
+            .with_child((
+                Mesh3d(assets.key.clone()),
+                MeshMaterial3d(assets.key_material.clone()),
+                Transform::from_xyz(0.0, 1.2, 0.0),
+            ));
+    }
+
+    for entity in levers {
+        commands
+            .entity(entity)
+            .insert(Visibility::default())
+            .with_children(|parent| {
+                parent.spawn((
+                    Mesh3d(assets.post.clone()),
+                    MeshMaterial3d(assets.lever_material.clone()),
+                    Transform::from_xyz(0.0, 0.6, 0.0),
+                ));
+                parent.spawn((
+                    LeverHandle,
+                    Mesh3d(assets.handle.clone()),
+                    MeshMaterial3d(assets.lever_material.clone()),
+                    Transform::from_xyz(0.0, 1.2, 0.0)
+                        .with_rotation(Quat::from_rotation_z(TAU / 8.0)),
+                ));
+            });
+    }
+}
+
+fn animate(
+    keys: Query<&Children, With<GateKey>>,
+    mut transforms: Query<&mut Transform>,
+    time: Res<Time>,
+) {
+    for children in keys {
+        for child in children {
+            if let Ok(mut transform) = transforms.get_mut(*child) {
+                let angle = 0.5 * time.elapsed_secs() * TAU;
+                transform.rotation =
+                    Quat::from_rotation_y(angle) * Quat::from_rotation_x(TAU / 8.0);
+            }
+        }
+    }
+}
+
+fn pick_keys(
+    mut commands: Commands,
+    player: Single<(&Player, &Transform)>,
+    keys: Query<(Entity, &GateKey, &Transform)>,
+    mut open_gates: EventWriter<OpenGates>,
+    mut user_notify: EventWriter<UserNotify>,
+) {
+    let pickup_dist = 3.0;
+    let (player, player_transform) = player.into_inner();
+
+    for (entity, key, transform) in keys {
+        let can_pickup = transform
+            .translation
+            .xz()
+            .distance(player_transform.translation.xz())
+            <= pickup_dist;
+        if can_pickup {
+            user_notify.write(UserNotify(
+                "Нажмите 'E'".to_string(),
+                "Чтобы подобрать ключ".to_string(),
+            ));
+        }
+        if can_pickup && player.interaction {
+            commands.entity(entity).despawn();
+            open_gates.write(OpenGates(key.0));
+        }
+    }
+}
+
+fn pull_levers(
+    mut commands: Commands,
+    player: Single<(&Player, &Transform)>,
+    levers: Query<(Entity, &GateLever, &Transform, &Children)>,
+    mut handles: Query<&mut Transform, (With<LeverHandle>, Without<GateLever>)>,
+    mut open_gates: EventWriter<OpenGates>,
+    mut user_notify: EventWriter<UserNotify>,
+) {
+    let use_dist = 3.0;
+    let (player, player_transform) = player.into_inner();
+
+    for (entity, lever, transform, children) in levers {
+        let can_use = transform
+            .translation
+            .xz()
+            .distance(player_transform.translation.xz())
+            <= use_dist;
+        if can_use {
+            user_notify.write(UserNotify(
+                "Нажмите 'E'".to_string(),
+                "Чтобы потянуть рычаг".to_string(),
+            ));
+        }
+        if can_use && player.interaction {
+            commands.entity(entity).remove::<GateLever>();
+            for child in children {
+                if let Ok(mut handle) = handles.get_mut(*child) {
+                    handle.rotation = Quat::from_rotation_z(-TAU / 8.0);
+                }
+            }
+            open_gates.write(OpenGates(lever.0));
+        }
+    }
+}
+
+// Enemies that are dying already have `DeferDespawn`, and until the floor's enemies
+// are spawned every part looks cleared.
+fn check_cleared(
+    level: Res<Level>,
+    enemies: Query<&PartEnemy, Without<DeferDespawn>>,
+    mut open_gates: EventWriter<OpenGates>,
+) {
+    let mut parts = level
+        .gates()
+        .iter()
+        .filter(|gate| !gate.open && gate.condition == GateCondition::Clear)
+        .map(|gate| gate.part)
+        .collect::<Vec<_>>();
+    parts.sort_unstable();
+    parts.dedup();
+
+    for part in parts {
+        if !enemies.iter().any(|enemy| enemy.0 == part) {
+            open_gates.write(OpenGates(part));
+        }
+    }
+}
+
+fn open_gates(
+    mut commands: Commands,
+    mut events: EventReader<OpenGates>,
+    mut level: ResMut<Level>,
+    barriers: Query<(Entity, &GateBarrier)>,
+    mut carved: EventWriter<TerrainCarved>,
+) {
+    for OpenGates(part) in events.read() {
+        let opened = level.open_gates(*part);
+        if opened.is_empty() {
+            continue;
+        }
+        info!("Opened {} gates of part {part}", opened.len());
+        // the walls are part of the terrain height, the chunks under them are remeshed
+        for &gate in &opened {
+            carved.write(TerrainCarved(level.gates()[gate].bounds()));
+        }
+        for (entity, barrier) in barriers {
+            if opened.contains(&barrier.0) {
+                commands.entity(entity).despawn();
+            }
+        }
+    }
+}
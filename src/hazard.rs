@@ -0,0 +1,155 @@
+use std::{f32::consts::TAU, time::Duration};
+
+use bevy::{prelude::*, time::common_conditions::on_timer};
+use twg_2025_1::level::{
+    Level,
+    hazards::{Hazard, HazardKind},
+};
+
+use crate::{GameState, enemy::Enemy, player::Player, projectile::apply_damage, terrain::Physics};
+
+pub struct HazardPlugin;
+
+impl Plugin for HazardPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, init);
+        app.add_systems(Update, setup);
+        app.add_systems(Update, slow);
+        app.add_systems(
+            Update,
+            damage.run_if(on_timer(Duration::from_secs_f32(DAMAGE_TICK))),
+        );
+    }
+}
+
+const DAMAGE_TICK: f32 = 0.5;
+
+// Hazard kinds the creature walks through freely, usually the one of its home biome.
+#[derive(Component)]
+pub struct HazardImmune(pub Vec<HazardKind>);
+
+#[derive(Component)]
+pub struct HazardZone(pub Hazard);
+
+#[derive(Resource)]
+struct HazardAssets {
+    pool: Handle<Mesh>,
+    cloud: Handle<Mesh>,
+    materials: [Handle<StandardMaterial>; HazardKind::ALL.len()],
+}
+
+fn init(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    let materials = HazardKind::ALL.map(|kind| {
+        materials.add(match kind {
+            HazardKind::Acid => StandardMaterial {
+                base_color: Color::srgba(0.3, 0.9, 0.1, 0.8),
+                emissive: LinearRgba::rgb(0.4, 1.5, 0.1),
+                perceptual_roughness: 0.1,
+                alpha_mode: AlphaMode::Blend,
+                ..Default::default()
+            },
+            HazardKind::Mud => StandardMaterial {
+                base_color: Color::srgb(0.12, 0.08, 0.04),
+                perceptual_roughness: 0.2,
+                ..Default::default()
+            },
+            HazardKind::Spores => StandardMaterial {
+                base_color: Color::srgba(0.6, 0.3, 0.8, 0.25),
+                emissive: LinearRgba::rgb(0.3, 0.1, 0.5),
+                alpha_mode: AlphaMode::Blend,
+                unlit: true,
+                ..Default::default()
+            },
+        })
+    });
+
+    commands.insert_resource(HazardAssets {
+        pool: meshes.add(Circle::new(1.0)),
+        cloud: meshes.add(Sphere::new(1.0)),
+        materials,
+    });
+}
+
+fn setup(
+    mut commands: Commands,
+    assets: Res<HazardAssets>,
+    zones: Query<(Entity, &HazardZone), Added<HazardZone>>,
+) {
+    for (entity, zone) in zones {
+        let Hazard { kind, radius, .. } = zone.0;
+        let (mesh, transform) = match kind {
+            HazardKind::Acid | HazardKind::Mud => (
+                assets.pool.clone(),
+                Transform::from_xyz(0.0, 0.05, 0.0)
+                    .with_rotation(Quat::from_rotation_x(-TAU / 4.0))
+                    .with_scale(Vec3::splat(radius)),
+            ),
+            HazardKind::Spores => (
+                assets.cloud.clone(),
+                Transform::from_xyz(0.0, 1.0, 0.0).with_scale(Vec3::new(
+                    radius,
+                    0.6 * radius,
+                    radius,
+                )),
+            ),
+        };
+
+        commands
+            .entity(entity)
+            .insert(Visibility::default())
+            .with_child((
+                Mesh3d(mesh),
+                MeshMaterial3d(assets.materials[kind as usize].clone()),
+                transform,
+            ));
+    }
+}
+
+fn hazard_at(
+    level: &Level,
+    transform: &Transform,
+    immune: Option<&HazardImmune>,
+) -> Option<HazardKind> {
+    let immune = immune.map_or(&[][..], |immune| &immune.0);
+    level
+        .hazard(transform.translation.xz())
+        .filter(|kind| !immune.contains(kind))
+}
+
+fn slow(
+    level: Res<Level>,
+    mut creatures: Query<(&Transform, &mut Physics, Option<&HazardImmune>)>,
+) {
+    for (transform, mut physics, immune) in &mut creatures {
+        physics.speed_scale =
+            hazard_at(&level, transform, immune).map_or(1.0, |kind| kind.speed_factor());
+    }
+}
+
+type Creature<'a> = (Entity, &'a Transform, Option<&'a HazardImmune>);
+
+fn damage(
+    mut commands: Commands,
+    level: Res<Level>,
+    game_state: Res<GameState>,
+    player: Query<Creature, With<Player>>,
+    enemies: Query<Creature, With<Enemy>>,
+) {
+    if !matches!(*game_state, GameState::Running) {
+        return;
+    }
+
+    for (entity, transform, immune) in player.iter().chain(&enemies) {
+        let Some(kind) = hazard_at(&level, transform, immune) else {
+            continue;
+        };
+        let damage = kind.damage_per_second() * DAMAGE_TICK;
+        if damage > 0.0 {
+            apply_damage(&mut commands, entity, damage, None);
+        }
+    }
+}
@@ -3,6 +3,7 @@ use std::f32::consts::TAU;
 use bevy::prelude::*;
 
 use crate::{
+    floor::{Floors, PartPickup},
     model_loader::{LoadModel, ReadyAction},
     player::Player,
     ui::UserNotify,
@@ -45,13 +46,14 @@ fn animate(mut hearts: Query<&mut Transform, With<Heart>>, time: Res<Time>) {
 fn update(
     mut commands: Commands,
     player: Single<(&mut Player, &Transform)>,
-    hearts: Query<(Entity, &Transform), With<Heart>>,
+    hearts: Query<(Entity, &Transform, Option<&PartPickup>), With<Heart>>,
+    mut floors: ResMut<Floors>,
     mut user_notify: EventWriter<UserNotify>,
 ) {
     let pickup_dist = 3.0;
     let (mut player, player_transform) = player.into_inner();
 
-    for (entity, transform) in hearts {
+    for (entity, transform, pickup) in hearts {
         let can_pickup = transform
             .translation
             .xz()
@@ -65,6 +67,9 @@ fn update(
         }
         if can_pickup && player.interaction {
             commands.entity(entity).despawn();
+            if let Some(pickup) = pickup {
+                floors.take_pickup(pickup);
+            }
             player.max_hp += 100.0;
             player.hp = player.max_hp;
         }
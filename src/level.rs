@@ -1,15 +1,19 @@
-use std::{collections::BinaryHeap, f32::consts::E};
+use std::{
+    collections::{BinaryHeap, HashMap},
+    f32::consts::E,
+    fmt,
+    ops::Range,
+};
 
 use bevy::prelude::*;
 use fast_poisson::Poisson2D;
 use imageproc::{
     distance_transform::euclidean_squared_distance_transform,
-    drawing::{draw_filled_rect_mut, draw_line_segment_mut},
+    drawing::draw_line_segment_mut,
     filter,
     image::{
         GrayImage, ImageBuffer, Luma, LumaA, Pixel, Primitive, Rgb, Rgba, imageops::sample_bilinear,
     },
-    rect,
 };
 use kiddo::{KdTree, SquaredEuclidean};
 use petgraph::{
@@ -17,9 +21,29 @@ use petgraph::{
     algo::min_spanning_tree,
     data::Element,
     graph::NodeIndex,
-    visit::{EdgeRef, IntoNodeReferences},
+    visit::{Bfs, EdgeRef, IntoNodeReferences},
+};
+use rand::{Rng, SeedableRng, rngs::StdRng};
+use serde::{Deserialize, Serialize};
+
+use crate::level::{
+    creatures::CreatureKind,
+    gates::{Gate, GateCondition},
+    hazards::{Hazard, HazardKind, PartHazard},
+    navigation::FlowField,
+    props::Prop,
 };
 
+pub mod cache;
+pub mod creatures;
+pub mod deform;
+pub mod gates;
+pub mod hazards;
+pub mod layout;
+pub mod navigation;
+pub mod props;
+pub mod run;
+
 fn delaunay(points: Vec<Vec2>) -> Graph<Vec2, f32, Undirected> {
     let mut graph = Graph::new_undirected();
 
@@ -115,6 +139,7 @@ fn graph(points: Vec<Vec2>, ratio: f32) -> Graph<Vec2, f32, Undirected> {
     graph
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Deserialize, Serialize)]
 pub enum LevelBiome {
     Safe,
     Home,
@@ -127,6 +152,27 @@ pub enum LevelBiome {
 }
 
 impl LevelBiome {
+    pub const ALL: [Self; 8] = [
+        Self::Safe,
+        Self::Home,
+        Self::Forest,
+        Self::Cave,
+        Self::Mushroom,
+        Self::Temple,
+        Self::Meat,
+        Self::Boss,
+    ];
+
+    pub fn terrain_profile(&self) -> TerrainProfile {
+        match self {
+            Self::Cave => TerrainProfile::Cliff,
+            Self::Temple => TerrainProfile::Terrace,
+            Self::Meat => TerrainProfile::Mounds,
+            Self::Mushroom => TerrainProfile::Organic,
+            Self::Safe | Self::Home | Self::Forest | Self::Boss => TerrainProfile::Slope,
+        }
+    }
+
     fn to_pixel_channel(&self) -> usize {
         match self {
             Self::Safe => BiomePixel::AREA_SAFE,
@@ -141,11 +187,203 @@ impl LevelBiome {
     }
 }
 
+// Only shapes the walls, the road stays a signed distance field so `Level::can_walk`
+// and terrain physics behave the same in every biome.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TerrainProfile {
+    Slope,
+    Cliff,
+    Terrace,
+    Mounds,
+    Organic,
+}
+
+impl TerrainProfile {
+    // `wall` is the distance from the road edge, `slope` is the default wall steepness.
+    // Every profile keeps a linear term so walls never get flat and normals still push out.
+    fn height(&self, wall: f32, slope: f32, point: Vec2, seed: u32) -> f32 {
+        let linear = slope * wall;
+        match self {
+            Self::Slope => linear,
+            Self::Cliff => 0.5 * linear + 10.0 * (1.0 - (-wall).exp()),
+            Self::Terrace => {
+                let step = 3.0;
+                let t = linear / step;
+                let riser = ((t.fract() - 0.6) / 0.4).clamp(0.0, 1.0);
+                step * (t.floor() + riser * riser * (3.0 - 2.0 * riser)) + 0.15 * linear
+            }
+            Self::Mounds => 0.6 * linear + 2.0 * (1.0 - (0.6 * wall).cos()),
+            Self::Organic => {
+                linear * (0.7 + 0.6 * value_noise(seed, point * 0.08))
+                    + 2.5 * value_noise(seed ^ 1, point * 0.3) * (0.5 * wall).min(1.0)
+            }
+        }
+    }
+}
+
+fn value_noise(seed: u32, point: Vec2) -> f32 {
+    let cell = point.floor();
+    let t = point - cell;
+    let t = t * t * (3.0 - 2.0 * t);
+
+    let hash = |x: f32, y: f32| {
+        let mut hash = (x as i32 as u32).wrapping_mul(0x8da6b343)
+            ^ (y as i32 as u32).wrapping_mul(0xd8163841)
+            ^ seed.wrapping_mul(0xcb1ab31f);
+        hash ^= hash >> 13;
+        hash = hash.wrapping_mul(0x5bd1e995);
+        hash ^= hash >> 15;
+        hash as f32 / u32::MAX as f32
+    };
+
+    let bottom = hash(cell.x, cell.y).lerp(hash(cell.x + 1.0, cell.y), t.x);
+    let top = hash(cell.x, cell.y + 1.0).lerp(hash(cell.x + 1.0, cell.y + 1.0), t.x);
+    bottom.lerp(top, t.y)
+}
+
+#[derive(Clone, Default, Debug, Deserialize)]
+pub enum PartRegion {
+    #[default]
+    Rect,
+    Polygon(Vec<Vec2>),
+    Circle {
+        radius: f32,
+    },
+    Voronoi,
+}
+
+fn polygon_contains(polygon: &[Vec2], point: Vec2) -> bool {
+    let mut inside = false;
+    for (a, b) in polygon.iter().zip(polygon.iter().cycle().skip(1)) {
+        if (a.y > point.y) != (b.y > point.y)
+            && point.x < a.x + (point.y - a.y) * (b.x - a.x) / (b.y - a.y)
+        {
+            inside = !inside;
+        }
+    }
+    inside
+}
+
+fn polygon_area(polygon: &[Vec2]) -> f32 {
+    let twice = polygon
+        .iter()
+        .zip(polygon.iter().cycle().skip(1))
+        .map(|(a, b)| a.perp_dot(*b))
+        .sum::<f32>();
+    0.5 * twice.abs()
+}
+
+// Area of the convex hull the triangulation covers, zero for collinear points.
+fn hull_area(points: &[Vec2]) -> f32 {
+    let triangulation = delaunator::triangulate(
+        &points
+            .iter()
+            .map(|p| delaunator::Point {
+                x: p.x as f64,
+                y: p.y as f64,
+            })
+            .collect::<Vec<_>>(),
+    );
+    triangulation
+        .triangles
+        .chunks_exact(3)
+        .map(|triangle| {
+            let [a, b, c] = [0, 1, 2].map(|idx| points[triangle[idx]]);
+            0.5 * (b - a).perp_dot(c - a).abs()
+        })
+        .sum()
+}
+
+// Whether two edges of the outline cross, the ones sharing a corner are skipped.
+fn polygon_self_intersects(polygon: &[Vec2]) -> bool {
+    let edges = polygon
+        .iter()
+        .zip(polygon.iter().cycle().skip(1))
+        .collect::<Vec<_>>();
+    let side = |a: Vec2, b: Vec2, p: Vec2| (b - a).perp_dot(p - a).signum();
+
+    edges.iter().enumerate().any(|(i, (a, b))| {
+        edges
+            .iter()
+            .enumerate()
+            .skip(i + 2)
+            .filter(|(j, _)| !(i == 0 && *j == edges.len() - 1))
+            .any(|(_, (c, d))| {
+                side(**a, **b, **c) != side(**a, **b, **d)
+                    && side(**c, **d, **a) != side(**c, **d, **b)
+            })
+    })
+}
+
 pub struct LevelPart {
     graph: Graph<Vec2, f32, Undirected>,
+    nodes: Range<usize>,
     bounds: Rect,
     radius: f32,
     biome: LevelBiome,
+    region: PartRegion,
+    blend: f32,
+    hazard: Option<PartHazard>,
+    gate: Option<GateCondition>,
+}
+
+impl LevelPart {
+    pub fn biome(&self) -> LevelBiome {
+        self.biome
+    }
+
+    pub fn bounds(&self) -> Rect {
+        self.bounds
+    }
+
+    pub fn nodes(&self) -> Range<usize> {
+        self.nodes.clone()
+    }
+
+    pub fn region(&self) -> &PartRegion {
+        &self.region
+    }
+
+    pub fn blend(&self) -> f32 {
+        self.blend
+    }
+
+    pub fn hazard(&self) -> Option<PartHazard> {
+        self.hazard
+    }
+
+    pub fn gate(&self) -> Option<GateCondition> {
+        self.gate
+    }
+
+    // Voronoi cells are limited to the part bounds grown by one node spacing,
+    // the caller still has to check which part owns the nearest node.
+    fn region_bounds(&self) -> Rect {
+        let center = self.bounds.center();
+        match &self.region {
+            PartRegion::Rect => self.bounds,
+            PartRegion::Polygon(points) => points.iter().fold(
+                Rect {
+                    min: Vec2::MAX,
+                    max: Vec2::MIN,
+                },
+                |rect, point| rect.union_point(center + point),
+            ),
+            PartRegion::Circle { radius } => {
+                Rect::from_center_half_size(center, Vec2::splat(*radius))
+            }
+            PartRegion::Voronoi => self.bounds.inflate(self.radius),
+        }
+    }
+
+    fn region_contains(&self, point: Vec2) -> bool {
+        let local = point - self.bounds.center();
+        match &self.region {
+            PartRegion::Polygon(points) => polygon_contains(points, local),
+            PartRegion::Circle { radius } => local.length_squared() <= radius * radius,
+            PartRegion::Rect | PartRegion::Voronoi => self.region_bounds().contains(point),
+        }
+    }
 }
 
 pub struct LevelPartBuilder {
@@ -155,10 +393,15 @@ pub struct LevelPartBuilder {
     fill_ratio: f32,
     biome: LevelBiome,
     points: Option<Vec<Vec2>>,
+    region: PartRegion,
+    blend: f32,
+    hazard: Option<PartHazard>,
+    gate: Option<GateCondition>,
 }
 
 impl LevelPartBuilder {
     const GAP: Vec2 = Vec2::new(25.0, 25.0);
+    pub const DEFAULT_BLEND: f32 = 2.0;
 
     pub fn new(biome: LevelBiome) -> Self {
         Self {
@@ -168,6 +411,10 @@ impl LevelPartBuilder {
             fill_ratio: 0.0,
             biome,
             points: None,
+            region: PartRegion::Rect,
+            blend: Self::DEFAULT_BLEND,
+            hazard: biome.hazard(),
+            gate: None,
         }
     }
 
@@ -192,16 +439,42 @@ impl LevelPartBuilder {
         self
     }
 
+    pub fn with_region(mut self, region: PartRegion) -> Self {
+        self.region = region;
+        self
+    }
+
+    pub fn with_blend(mut self, blend: f32) -> Self {
+        self.blend = blend;
+        self
+    }
+
+    // Replaces the biome's default hazard, `None` keeps the part's roads clear.
+    pub fn with_hazard(mut self, hazard: Option<PartHazard>) -> Self {
+        self.hazard = hazard;
+        self
+    }
+
+    // Closes the roads to every part added after this one until `condition` is met.
+    pub fn with_gate(mut self, condition: GateCondition) -> Self {
+        self.gate = Some(condition);
+        self
+    }
+
     fn estimate_radius(&self) -> f32 {
-        (2.0 * self.width * self.height / (E * self.count as f32)).sqrt()
+        // explicit points replace the count
+        let count = self.points.as_ref().map_or(self.count, Vec::len);
+        (2.0 * self.width * self.height / (E * count as f32)).sqrt()
     }
 
-    pub fn build(self) -> LevelPart {
+    pub fn build(self, seed: u64) -> LevelPart {
         let radius = self.estimate_radius();
         let points = match self.points {
             Some(points) => points,
+            None if self.count == 0 => vec![],
             None => Poisson2D::new()
                 .with_dimensions([self.width as f64, self.height as f64], radius as f64)
+                .with_seed(seed)
                 .iter()
                 .map(|[x, y]| Vec2 {
                     x: x as f32 - 0.5 * self.width as f32,
@@ -215,15 +488,23 @@ impl LevelPartBuilder {
             Vec2::new(self.width as f32, self.height as f32) + Self::GAP * 2.0,
         );
 
+        let graph = graph(points, self.fill_ratio);
+
         LevelPart {
-            graph: graph(points, self.fill_ratio),
+            nodes: 0..graph.node_count(),
+            graph,
             bounds,
             radius,
             biome: self.biome,
+            region: self.region,
+            blend: self.blend,
+            hazard: self.hazard,
+            gate: self.gate,
         }
     }
 }
 
+#[derive(Clone, Copy, Debug, Deserialize)]
 pub enum PartAlign {
     Left,
     Right,
@@ -231,23 +512,130 @@ pub enum PartAlign {
     Down,
 }
 
+#[derive(Clone, Copy)]
+pub struct RayHit {
+    pub point: Vec3,
+    pub normal: Vec3,
+    pub distance: f32,
+    pub biome: BiomePixel,
+}
+
 #[derive(Resource)]
 pub struct Level {
     pub graph: Graph<Vec2, f32, Undirected>,
+    seed: u64,
+    parts: Vec<LevelPart>,
     kd_terrain: KdTree<f32, 2>,
-    kd_creatures: KdTree<f32, 2>,
+    kd_creatures: [KdTree<f32, 2>; CreatureKind::ALL.len()],
+    creatures: HashMap<Entity, (CreatureKind, Vec2)>,
+    flow_fields: HashMap<(Entity, u8), FlowField>,
     bounds: Rect,
     scale: f32,
     biome_map: ImageBuffer<BiomePixel, Vec<f32>>,
     height_map: ImageBuffer<Luma<f32>, Vec<f32>>,
     normal_map: ImageBuffer<Rgb<f32>, Vec<f32>>,
+    slopes: ImageBuffer<Luma<f32>, Vec<f32>>,
+    props: Vec<Prop>,
+    hazards: Vec<Hazard>,
+    kd_hazards: KdTree<f32, 2>,
+    // Length of every graph edge covered by each hazard kind.
+    edge_hazards: Vec<[f32; HazardKind::ALL.len()]>,
+    gates: Vec<Gate>,
+}
+
+fn normal_at(
+    height_map: &ImageBuffer<Luma<f32>, Vec<f32>>,
+    scale: f32,
+    x: u32,
+    y: u32,
+) -> Rgb<f32> {
+    let (width, height) = height_map.dimensions();
+    let max_pos = UVec2::new(width, height).as_ivec2() - 1;
+    let pos = UVec2::new(x, y).as_ivec2();
+
+    let pos_r = IVec2::new(pos.x + 1, pos.y).min(max_pos).as_uvec2();
+    let pos_l = IVec2::new(pos.x - 1, pos.y).max(IVec2::ZERO).as_uvec2();
+    let pos_t = IVec2::new(pos.x, pos.y + 1).min(max_pos).as_uvec2();
+    let pos_b = IVec2::new(pos.x, pos.y - 1).max(IVec2::ZERO).as_uvec2();
+
+    let h_r = height_map.get_pixel(pos_r.x, pos_r.y).0[0];
+    let h_l = height_map.get_pixel(pos_l.x, pos_l.y).0[0];
+    let h_t = height_map.get_pixel(pos_t.x, pos_t.y).0[0];
+    let h_b = height_map.get_pixel(pos_b.x, pos_b.y).0[0];
+
+    let dh_dx = (h_r - h_l) * scale * 0.5;
+    let dh_dy = (h_t - h_b) * scale * 0.5;
+
+    Rgb(Vec3::new(-dh_dx, 1.0, -dh_dy)
+        .normalize_or_zero()
+        .to_array())
+}
+
+// Side of the tiles the height map's slope is bounded over, in texels.
+const SLOPE_TILE: u32 = 16;
+
+// Upper bound of the gradient of the bilinearly sampled, floor-clamped height map, one
+// pixel per tile. Each tile also covers its neighbours, so the bound holds for a step that
+// starts in the tile and is shorter than one.
+fn slope_tiles(
+    height_map: &ImageBuffer<Luma<f32>, Vec<f32>>,
+    scale: f32,
+) -> ImageBuffer<Luma<f32>, Vec<f32>> {
+    let (width, height) = height_map.dimensions();
+    let (tiles_x, tiles_y) = (width.div_ceil(SLOPE_TILE), height.div_ceil(SLOPE_TILE));
+    let h = |x: u32, y: u32| height_map.get_pixel(x, y).0[0].max(0.0);
+
+    let mut own = ImageBuffer::<Luma<f32>, Vec<f32>>::new(tiles_x, tiles_y);
+    for y in 0..height {
+        for x in 0..width {
+            let mut diff = 0.0_f32;
+            if x + 1 < width {
+                diff = diff.max((h(x + 1, y) - h(x, y)).abs());
+            }
+            if y + 1 < height {
+                diff = diff.max((h(x, y + 1) - h(x, y)).abs());
+            }
+            let tile = &mut own.get_pixel_mut(x / SLOPE_TILE, y / SLOPE_TILE).0[0];
+            *tile = tile.max(diff * scale * std::f32::consts::SQRT_2);
+        }
+    }
+
+    ImageBuffer::from_fn(tiles_x, tiles_y, |x, y| {
+        let mut slope = 0.0_f32;
+        for ny in y.saturating_sub(1)..=(y + 1).min(tiles_y - 1) {
+            for nx in x.saturating_sub(1)..=(x + 1).min(tiles_x - 1) {
+                slope = slope.max(own.get_pixel(nx, ny).0[0]);
+            }
+        }
+        Luma([slope])
+    })
 }
 
 impl Level {
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn parts(&self) -> &[LevelPart] {
+        &self.parts
+    }
+
     pub fn bounds(&self) -> Rect {
         self.bounds
     }
 
+    pub fn biome_map(&self) -> &ImageBuffer<BiomePixel, Vec<f32>> {
+        &self.biome_map
+    }
+
+    pub fn height_map(&self) -> &ImageBuffer<Luma<f32>, Vec<f32>> {
+        &self.height_map
+    }
+
+    pub fn normal_map(&self) -> &ImageBuffer<Rgb<f32>, Vec<f32>> {
+        &self.normal_map
+    }
+
     pub fn texture_size(&self) -> Vec2 {
         self.scale * self.bounds.size()
     }
@@ -269,22 +657,34 @@ impl Level {
         *self.biome_map.get_pixel(pos.x, pos.y)
     }
 
+    // Closed gates are walls laid over the height map, so everything that samples
+    // heights and normals here treats them like terrain.
     pub fn height(&self, world_pos: Vec2) -> f32 {
         let pos = self.world_to_uv(world_pos);
-        sample_bilinear(&self.height_map, pos.x, pos.y).unwrap().0[0]
+        let height = sample_bilinear(&self.height_map, pos.x, pos.y).unwrap().0[0];
+        self.gate_height(world_pos)
+            .map_or(height, |(gate, _)| height.max(gate))
     }
 
     pub fn normal_3d(&self, world_pos: Vec2) -> Vec3 {
         let pos = self.world_to_uv(world_pos);
-        if sample_bilinear(&self.height_map, pos.x, pos.y).unwrap().0[0] <= 0.0 {
-            Vec3::Y
-        } else {
-            Vec3::from(sample_bilinear(&self.normal_map, pos.x, pos.y).unwrap().0)
+        let height = sample_bilinear(&self.height_map, pos.x, pos.y).unwrap().0[0];
+        match self.gate_height(world_pos) {
+            Some((gate, away)) if gate > height.max(0.0) => {
+                Vec3::new(away.x, 1.0, away.y).normalize()
+            }
+            _ if height <= 0.0 => Vec3::Y,
+            _ => Vec3::from(sample_bilinear(&self.normal_map, pos.x, pos.y).unwrap().0),
         }
     }
 
     pub fn normal_2d(&self, world_pos: Vec2) -> Vec2 {
         let pos = self.world_to_uv(world_pos);
+        if let Some((gate, away)) = self.gate_height(world_pos)
+            && gate > sample_bilinear(&self.height_map, pos.x, pos.y).unwrap().0[0]
+        {
+            return away;
+        }
         let [x, _, z] = sample_bilinear(&self.normal_map, pos.x, pos.y).unwrap().0;
         Vec2::new(x, z).normalize_or_zero()
     }
@@ -301,22 +701,84 @@ impl Level {
         max
     }
 
-    pub fn raycast(
-        &self,
-        origin: Vec3,
-        dir: Dir3,
-        step: f32,
-        max_dist: f32,
-        binary_steps: u32,
-    ) -> Vec3 {
-        let mut pos = origin;
-        while pos.distance_squared(origin) < max_dist * max_dist {
-            if self.height(pos.xz()).max(0.0) > pos.y {
-                return self.binary_search(pos - dir * step, pos, binary_steps);
+    fn slope(&self, world_pos: Vec2) -> f32 {
+        let (width, height) = self.slopes.dimensions();
+        let tile = (self.world_to_texture(world_pos).as_uvec2() / SLOPE_TILE)
+            .min(UVec2::new(width, height) - 1);
+        self.slopes.get_pixel(tile.x, tile.y).0[0]
+    }
+
+    // Lets the terrain between texels `min` and `max` rise with a slope of up to `slope`,
+    // for edits made after the level was built.
+    pub(super) fn raise_slope(&mut self, min: UVec2, max: UVec2, slope: f32) {
+        let (width, height) = self.slopes.dimensions();
+        let min = (min / SLOPE_TILE).saturating_sub(UVec2::ONE);
+        let max = (max / SLOPE_TILE + 1).min(UVec2::new(width, height) - 1);
+        for y in min.y..=max.y {
+            for x in min.x..=max.x {
+                let tile = &mut self.slopes.get_pixel_mut(x, y).0[0];
+                *tile = tile.max(slope);
+            }
+        }
+    }
+
+    // Sphere tracing: the slope of the tiles around the ray bounds how fast it can approach
+    // the surface. Steps stay short of a tile so that bound holds, and never drop below half
+    // a texel so bilinear features can't be skipped.
+    pub fn raycast(&self, origin: Vec3, dir: Dir3, max_dist: f32) -> Option<RayHit> {
+        let min_step = 0.5 * self.pixel_size();
+        let max_step = (SLOPE_TILE - 1) as f32 * self.pixel_size() / dir.xz().length();
+
+        let mut prev_dist = 0.0;
+        let mut dist = 0.0;
+        loop {
+            let pos = origin + dir * dist;
+            let gap = pos.y - self.height(pos.xz()).max(0.0);
+
+            if gap <= 0.0 {
+                let point = if dist > 0.0 {
+                    self.binary_search(origin + dir * prev_dist, pos, 8)
+                } else {
+                    pos
+                };
+                return Some(RayHit {
+                    point,
+                    normal: self.normal_3d(point.xz()),
+                    distance: origin.distance(point),
+                    biome: self.biome(point.xz()),
+                });
+            }
+
+            if dist >= max_dist {
+                return None;
             }
-            pos += dir * step;
+
+            let approach_rate = self.slope(pos.xz()) * dir.xz().length() - dir.y;
+            let step = if approach_rate > 0.0 {
+                (gap / approach_rate).clamp(min_step, max_step)
+            } else {
+                max_step
+            };
+            prev_dist = dist;
+            dist = (dist + step).min(max_dist);
         }
-        pos
+    }
+
+    pub fn raycast_many(
+        &self,
+        rays: impl IntoIterator<Item = (Vec3, Dir3)>,
+        max_dist: f32,
+    ) -> Vec<Option<RayHit>> {
+        rays.into_iter()
+            .map(|(origin, dir)| self.raycast(origin, dir, max_dist))
+            .collect()
+    }
+
+    pub fn has_line_of_sight(&self, from: Vec3, to: Vec3) -> bool {
+        let Ok(dir) = Dir3::new(to - from) else {
+            return true;
+        };
+        self.raycast(from, dir, from.distance(to)).is_none()
     }
 
     pub fn can_walk(&self, mut from: Vec2, to: Vec2, radius: f32) -> bool {
@@ -356,38 +818,61 @@ impl Level {
             .map(|node| self.graph.node_weight(node).cloned())
             .collect()
     }
-
-    pub fn clear_creatures(&mut self) {
-        self.kd_creatures = KdTree::new();
-    }
-
-    pub fn add_creature(&mut self, creature: Entity, pos: Vec3) {
-        self.kd_creatures
-            .add(&pos.xz().to_array(), creature.to_bits());
-    }
-
-    pub fn nearest_creatures(&self, count: usize, point: Vec3) -> Vec<(Entity, f32)> {
-        self.kd_creatures
-            .nearest_n::<SquaredEuclidean>(&point.xz().to_array(), count)
-            .into_iter()
-            .map(|neighbour| (Entity::from_bits(neighbour.item), neighbour.distance))
-            .collect()
-    }
 }
 
 pub struct LevelBuilder {
+    seed: u64,
+    rng: StdRng,
     graph: Graph<Vec2, f32, Undirected>,
     kd_terrain: KdTree<f32, 2>,
     bounds: Rect,
     parts: Vec<LevelPart>,
+    errors: Vec<LevelError>,
+}
+
+#[derive(Debug)]
+pub enum LevelError {
+    EmptyPart { part: usize },
+    TooFewPoints { part: usize, count: usize },
+    ZeroArea { part: usize, area: f32 },
+    SelfIntersecting { part: usize },
+    Overlap { part: usize, other: usize },
+    Unreachable { part: usize },
 }
 
+impl fmt::Display for LevelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::EmptyPart { part } => write!(f, "part {part} has no points"),
+            Self::TooFewPoints { part, count } => write!(
+                f,
+                "part {part} has {count} points, at least 3 are needed to triangulate"
+            ),
+            Self::ZeroArea { part, area } => write!(
+                f,
+                "part {part} covers an area of {area}, its points are collinear or coincide"
+            ),
+            Self::SelfIntersecting { part } => {
+                write!(f, "region outline of part {part} crosses itself")
+            }
+            Self::Overlap { part, other } => write!(f, "part {part} overlaps part {other}"),
+            Self::Unreachable { part } => write!(f, "part {part} is not reachable from part 0"),
+        }
+    }
+}
+
+impl std::error::Error for LevelError {}
+
 impl LevelBuilder {
     const BLACK: Luma<u8> = Luma([0]);
     const WHITE: Luma<u8> = Luma([255]);
+    const OVERLAP_EPSILON: f32 = 0.01;
+    const AREA_EPSILON: f32 = 0.01;
 
-    pub fn new() -> Self {
+    pub fn new(seed: u64) -> Self {
         Self {
+            seed,
+            rng: StdRng::seed_from_u64(seed),
             graph: Graph::new_undirected(),
             kd_terrain: KdTree::new(),
             bounds: Rect {
@@ -395,12 +880,63 @@ impl LevelBuilder {
                 max: Vec2::MIN,
             },
             parts: vec![],
+            errors: vec![],
+        }
+    }
+
+    pub fn add(&mut self, offset: Vec2, part: LevelPartBuilder) -> usize {
+        let part = self.build_part(part);
+        self.add_part(offset, part)
+    }
+
+    pub fn add_after(&mut self, after: usize, align: PartAlign, part: LevelPartBuilder) -> usize {
+        let part = self.build_part(part);
+        let after = &self.parts[after];
+        let edge = 0.5 * (after.bounds.size() + part.bounds.size());
+        let offset = after.bounds.center() - part.bounds.center();
+        let offset = match align {
+            PartAlign::Left => offset - Vec2::new(edge.x, 0.0),
+            PartAlign::Right => offset + Vec2::new(edge.x, 0.0),
+            PartAlign::Up => offset + Vec2::new(0.0, edge.y),
+            PartAlign::Down => offset - Vec2::new(0.0, edge.y),
+        };
+        self.add_part(offset, part)
+    }
+
+    fn build_part(&mut self, part: LevelPartBuilder) -> LevelPart {
+        let idx = self.parts.len();
+        match &part.points {
+            Some(points) if points.len() < 3 => self.errors.push(LevelError::TooFewPoints {
+                part: idx,
+                count: points.len(),
+            }),
+            Some(points) if hull_area(points) < Self::AREA_EPSILON => {
+                self.errors.push(LevelError::ZeroArea {
+                    part: idx,
+                    area: hull_area(points),
+                })
+            }
+            None if part.count == 0 => self.errors.push(LevelError::EmptyPart { part: idx }),
+            _ => {}
         }
+        if let PartRegion::Polygon(outline) = &part.region {
+            if polygon_self_intersects(outline) {
+                self.errors.push(LevelError::SelfIntersecting { part: idx });
+            } else if polygon_area(outline) < Self::AREA_EPSILON {
+                self.errors.push(LevelError::ZeroArea {
+                    part: idx,
+                    area: polygon_area(outline),
+                });
+            }
+        }
+        part.build(self.rng.random())
     }
 
-    pub fn add(&mut self, offset: Vec2, mut part: LevelPart) -> usize {
+    fn add_part(&mut self, offset: Vec2, mut part: LevelPart) -> usize {
         let idx_offset = self.graph.node_count();
 
+        part.nodes = idx_offset..idx_offset + part.graph.node_count();
+
         part.bounds = Rect {
             min: part.bounds.min + offset,
             max: part.bounds.max + offset,
@@ -478,17 +1014,14 @@ impl LevelBuilder {
         self.parts.len() - 1
     }
 
-    pub fn add_after(&mut self, after: usize, align: PartAlign, part: LevelPart) -> usize {
-        let after = &self.parts[after];
-        let edge = 0.5 * (after.bounds.size() + part.bounds.size());
-        let offset = after.bounds.center() - part.bounds.center();
-        let offset = match align {
-            PartAlign::Left => offset - Vec2::new(edge.x, 0.0),
-            PartAlign::Right => offset + Vec2::new(edge.x, 0.0),
-            PartAlign::Up => offset + Vec2::new(0.0, edge.y),
-            PartAlign::Down => offset - Vec2::new(0.0, edge.y),
-        };
-        self.add(offset, part)
+    fn nearest_part(&self, point: Vec2) -> Option<usize> {
+        let node = self
+            .kd_terrain
+            .nearest_one::<SquaredEuclidean>(&[point.x, point.y])
+            .item as usize;
+        self.parts
+            .iter()
+            .position(|part| part.nodes.contains(&node))
     }
 
     fn biome_map(&self, scale: f32) -> ImageBuffer<BiomePixel, Vec<f32>> {
@@ -502,28 +1035,105 @@ impl LevelBuilder {
             y: height,
         } = bounds.size().as_uvec2();
 
-        let mut biomes =
-            ImageBuffer::<BiomePixel, Vec<f32>>::from_pixel(width, height, BiomePixel::default());
+        // 0 is the background, part `idx` is painted as `idx + 1`, later parts paint over earlier ones
+        let mut labels = ImageBuffer::<Luma<u16>, Vec<u16>>::new(width, height);
+
+        for (idx, part) in self.parts.iter().enumerate() {
+            let region = part.region_bounds();
+            let min = ((region.min * scale).floor().as_ivec2() - bounds.min).max(IVec2::ZERO);
+            let max = ((region.max * scale).ceil().as_ivec2() - bounds.min).min(bounds.size());
+
+            for y in min.y..max.y {
+                for x in min.x..max.x {
+                    let point = (IVec2::new(x, y) + bounds.min).as_vec2() / scale + 0.5 / scale;
+                    let inside = part.region_contains(point)
+                        && (!matches!(part.region, PartRegion::Voronoi)
+                            || self.nearest_part(point) == Some(idx));
+                    if inside {
+                        labels.put_pixel(x as u32, y as u32, Luma([idx as u16 + 1]));
+                    }
+                }
+            }
+        }
+
+        let mut label_bounds = vec![
+            URect {
+                min: UVec2::MAX,
+                max: UVec2::ZERO,
+            };
+            self.parts.len() + 1
+        ];
+        for (x, y, label) in labels.enumerate_pixels() {
+            let rect = &mut label_bounds[label.0[0] as usize];
+            *rect = rect.union_point(UVec2::new(x, y));
+        }
 
-        for part in &self.parts {
-            let IVec2 { x, y } = (part.bounds.min * scale).as_ivec2() - bounds.min;
-            let UVec2 {
-                x: width,
-                y: height,
-            } = (part.bounds.size() * scale).as_uvec2();
+        let mut biomes = ImageBuffer::<BiomePixel, Vec<f32>>::from_pixel(
+            width,
+            height,
+            BiomePixel([0.0; BiomePixel::CHANNEL_COUNT as usize]),
+        );
+        let mut weights = ImageBuffer::<Luma<f32>, Vec<f32>>::new(width, height);
 
-            let mut pixel = BiomePixel([0.0; BiomePixel::CHANNEL_COUNT as usize]);
-            pixel.0[BiomePixel::RADIUS] = part.radius;
-            pixel.0[part.biome.to_pixel_channel()] = 1.0;
+        for (label, rect) in label_bounds.into_iter().enumerate() {
+            // nothing was painted with this label
+            if rect.min.x > rect.max.x {
+                continue;
+            }
 
-            draw_filled_rect_mut(
-                &mut biomes,
-                rect::Rect::at(x, y).of_size(width, height),
-                pixel,
+            let (pixel, blend) = match label.checked_sub(1).map(|idx| &self.parts[idx]) {
+                Some(part) => {
+                    let mut pixel = BiomePixel([0.0; BiomePixel::CHANNEL_COUNT as usize]);
+                    pixel.0[BiomePixel::RADIUS] = part.radius;
+                    pixel.0[part.biome.to_pixel_channel()] = 1.0;
+                    (pixel, part.blend)
+                }
+                None => (BiomePixel::default(), LevelPartBuilder::DEFAULT_BLEND),
+            };
+
+            // the gaussian kernel reaches 2 sigma, so the mask is cropped with a bit more than that
+            let sigma = blend * scale;
+            let margin = UVec2::splat((2.0 * sigma).ceil() as u32 + 1);
+            let min = rect.min.saturating_sub(margin);
+            let max = (rect.max + 1 + margin).min(UVec2::new(width, height));
+
+            let mask = ImageBuffer::<Luma<f32>, Vec<f32>>::from_fn(
+                max.x - min.x,
+                max.y - min.y,
+                |x, y| {
+                    Luma([
+                        (labels.get_pixel(min.x + x, min.y + y).0[0] as usize == label) as u8
+                            as f32,
+                    ])
+                },
             );
+            let mask = if sigma > 0.0 {
+                filter::gaussian_blur_f32(&mask, sigma)
+            } else {
+                mask
+            };
+
+            for (x, y, weight) in mask.enumerate_pixels() {
+                let weight = weight.0[0];
+                if weight <= 0.0 {
+                    continue;
+                }
+                let biome = biomes.get_pixel_mut(min.x + x, min.y + y);
+                for (channel, value) in biome.0.iter_mut().zip(pixel.0) {
+                    *channel += weight * value;
+                }
+                weights.get_pixel_mut(min.x + x, min.y + y).0[0] += weight;
+            }
+        }
+
+        for (biome, weight) in biomes.pixels_mut().zip(weights.pixels()) {
+            *biome = match weight.0[0] {
+                weight if weight > 0.0 => biome.map(|channel| channel / weight),
+                _ => BiomePixel::default(),
+            };
         }
 
-        filter::gaussian_blur_f32(&biomes, 8.0)
+        biomes
     }
 
     fn height_map(
@@ -560,6 +1170,8 @@ impl LevelBuilder {
 
         let distances = euclidean_squared_distance_transform(&image);
 
+        let seed = self.seed as u32 ^ (self.seed >> 32) as u32;
+
         ImageBuffer::from_fn(image.width(), image.height(), |x, y| {
             let dist = distances.get_pixel(x, y).0[0].sqrt() as f32 / scale;
             let biome = biome_map.get_pixel(x, y).0;
@@ -569,10 +1181,30 @@ impl LevelBuilder {
             let road_width = 0.25 * radius;
             let max_height = 0.5 * radius - road_width;
 
-            Luma([if dist < road_width {
-                dist - road_width
+            if dist < road_width {
+                return Luma([dist - road_width]);
+            }
+
+            let wall = dist - road_width;
+            let slope = 3.0 / max_height.powf(0.75);
+            let point = (IVec2::new(x as i32, y as i32) + bounds.min).as_vec2() / scale;
+
+            let (height, weight) = LevelBiome::ALL
+                .iter()
+                .map(|biome_kind| (biome_kind, biome[biome_kind.to_pixel_channel()].max(0.0)))
+                .filter(|(_, weight)| *weight > 0.0)
+                .fold((0.0, 0.0), |(height, total), (biome_kind, weight)| {
+                    let profile = biome_kind.terrain_profile();
+                    (
+                        height + weight * profile.height(wall, slope, point, seed),
+                        total + weight,
+                    )
+                });
+
+            Luma([if weight > 0.0 {
+                height / weight
             } else {
-                3.0 * (dist - road_width) / max_height.powf(0.75)
+                TerrainProfile::Slope.height(wall, slope, point, seed)
             }])
         })
     }
@@ -583,44 +1215,79 @@ impl LevelBuilder {
         height_map: &ImageBuffer<Luma<f32>, Vec<f32>>,
     ) -> ImageBuffer<Rgb<f32>, Vec<f32>> {
         let (width, height) = height_map.dimensions();
-        let max_pos = UVec2::new(width, height).as_ivec2() - 1;
         // let height_map = filter::gaussian_blur_f32(&height_map, 2.0);
-        ImageBuffer::from_fn(width, height, |x, y| {
-            let pos = UVec2::new(x, y).as_ivec2();
+        ImageBuffer::from_fn(width, height, |x, y| normal_at(height_map, scale, x, y))
+    }
 
-            let pos_r = IVec2::new(pos.x + 1, pos.y).min(max_pos).as_uvec2();
-            let pos_l = IVec2::new(pos.x - 1, pos.y).max(IVec2::ZERO).as_uvec2();
-            let pos_t = IVec2::new(pos.x, pos.y + 1).min(max_pos).as_uvec2();
-            let pos_b = IVec2::new(pos.x, pos.y - 1).max(IVec2::ZERO).as_uvec2();
+    fn validate(&mut self) -> Result<(), LevelError> {
+        if let Some(err) = self.errors.drain(..).next() {
+            return Err(err);
+        }
 
-            let h_r = height_map.get_pixel(pos_r.x, pos_r.y).0[0];
-            let h_l = height_map.get_pixel(pos_l.x, pos_l.y).0[0];
-            let h_t = height_map.get_pixel(pos_t.x, pos_t.y).0[0];
-            let h_b = height_map.get_pixel(pos_b.x, pos_b.y).0[0];
+        for (part, part_info) in self.parts.iter().enumerate() {
+            for (other, other_info) in self.parts.iter().enumerate().skip(part + 1) {
+                let overlap = part_info.bounds.intersect(other_info.bounds).size();
+                if overlap.min_element() > Self::OVERLAP_EPSILON {
+                    return Err(LevelError::Overlap { part, other });
+                }
+            }
+        }
 
-            let dh_dx = (h_r - h_l) * scale * 0.5;
-            let dh_dy = (h_t - h_b) * scale * 0.5;
+        let Some(start) = self
+            .parts
+            .first()
+            .and_then(|part| part.nodes.clone().next())
+        else {
+            return Ok(());
+        };
 
-            Rgb(Vec3::new(-dh_dx, 1.0, -dh_dy)
-                .normalize_or_zero()
-                .to_array())
-        })
+        let mut bfs = Bfs::new(&self.graph, NodeIndex::new(start));
+        while bfs.next(&self.graph).is_some() {}
+
+        for (part, part_info) in self.parts.iter().enumerate() {
+            if !part_info
+                .nodes
+                .clone()
+                .any(|node| bfs.discovered.contains(node))
+            {
+                return Err(LevelError::Unreachable { part });
+            }
+        }
+
+        Ok(())
     }
 
-    pub fn build(self, scale: f32) -> Level {
+    pub fn build(mut self, scale: f32) -> Result<Level, LevelError> {
+        self.validate()?;
+
         let biome_map = self.biome_map(scale);
         let height_map = self.height_map(scale, &biome_map);
         let normal_map = self.normal_map(scale, &height_map);
-        Level {
+        let slopes = slope_tiles(&height_map, scale);
+        let mut level = Level {
+            seed: self.seed,
+            parts: self.parts,
             graph: self.graph,
             kd_terrain: self.kd_terrain,
-            kd_creatures: KdTree::new(),
+            kd_creatures: Default::default(),
+            creatures: HashMap::new(),
+            flow_fields: HashMap::new(),
             bounds: self.bounds,
             scale,
             height_map,
             biome_map,
             normal_map,
-        }
+            slopes,
+            props: vec![],
+            hazards: vec![],
+            kd_hazards: KdTree::new(),
+            edge_hazards: vec![],
+            gates: vec![],
+        };
+        level.scatter_props();
+        level.place_hazards();
+        level.place_gates();
+        Ok(level)
     }
 }
 
@@ -649,6 +1316,39 @@ impl BiomePixel {
     pub const AREA_MEAT: usize = 7;
     pub const AREA_BOSS: usize = 8;
     pub const END_BIOME: usize = 9;
+
+    // Map colours of the biome channels, 0..255.
+    pub const COLORS: [[f32; 3]; Self::END_BIOME - Self::START_BIOME] = [
+        [200.0, 200.0, 200.0], // safe
+        [230.0, 200.0, 120.0], // home
+        [40.0, 140.0, 40.0],   // forest
+        [90.0, 90.0, 110.0],   // cave
+        [170.0, 60.0, 200.0],  // mushroom
+        [200.0, 170.0, 60.0],  // temple
+        [180.0, 40.0, 50.0],   // meat
+        [20.0, 20.0, 20.0],    // boss
+    ];
+
+    pub fn weight(&self, biome: LevelBiome) -> f32 {
+        self.0[biome.to_pixel_channel()].max(0.0)
+    }
+
+    // Biome colours mixed by their weights.
+    pub fn color(&self) -> [f32; 3] {
+        let biome = &self.0[Self::START_BIOME..Self::END_BIOME];
+        let total = biome
+            .iter()
+            .map(|w| w.max(0.0))
+            .sum::<f32>()
+            .max(f32::EPSILON);
+        let mut color = [0.0; 3];
+        for (weight, biome_color) in biome.iter().zip(Self::COLORS) {
+            for (c, biome_c) in color.iter_mut().zip(biome_color) {
+                *c += weight.max(0.0) * biome_c / total;
+            }
+        }
+        color
+    }
 }
 
 impl Pixel for BiomePixel {
@@ -779,3 +1479,230 @@ impl Pixel for BiomePixel {
         *self = *other;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A single small part, for tests that need a built level but don't care about its shape.
+    pub(super) fn small_level() -> Level {
+        let mut builder = LevelBuilder::new(7);
+        builder.add(
+            Vec2::ZERO,
+            LevelPartBuilder::new(LevelBiome::Home)
+                .with_size(40.0, 40.0)
+                .with_count(8)
+                .with_fill_ratio(0.2),
+        );
+        builder.build(1.0).unwrap()
+    }
+
+    fn square(size: f32) -> Vec<Vec2> {
+        vec![
+            Vec2::new(0.0, 0.0),
+            Vec2::new(size, 0.0),
+            Vec2::new(size, size),
+            Vec2::new(0.0, size),
+        ]
+    }
+
+    fn validate(part: LevelPartBuilder) -> Result<(), LevelError> {
+        let mut builder = LevelBuilder::new(1);
+        builder.add(Vec2::ZERO, part.with_size(20.0, 20.0));
+        builder.validate()
+    }
+
+    #[test]
+    fn polygon_helpers() {
+        assert_eq!(polygon_area(&square(2.0)), 4.0);
+        assert!(!polygon_self_intersects(&square(2.0)));
+        // the two sides of a bow tie cross in the middle
+        let bow_tie = [(0.0, 0.0), (2.0, 2.0), (2.0, 0.0), (0.0, 2.0)].map(Vec2::from);
+        assert!(polygon_self_intersects(&bow_tie));
+        assert_eq!(
+            hull_area(&[(0.0, 0.0), (1.0, 1.0), (2.0, 2.0)].map(Vec2::from)),
+            0.0
+        );
+    }
+
+    #[test]
+    fn accepts_a_square() {
+        let part = LevelPartBuilder::new(LevelBiome::Forest)
+            .with_points(square(10.0))
+            .with_region(PartRegion::Polygon(square(10.0)));
+        assert!(validate(part).is_ok());
+    }
+
+    #[test]
+    fn builds_from_points_alone() {
+        let mut builder = LevelBuilder::new(1);
+        builder.add(
+            Vec2::ZERO,
+            LevelPartBuilder::new(LevelBiome::Forest)
+                .with_size(10.0, 10.0)
+                .with_points(square(5.0)),
+        );
+        let level = builder.build(1.0).unwrap();
+        assert_eq!(level.graph.node_count(), 4);
+        assert!(level.parts[0].radius.is_finite());
+    }
+
+    #[test]
+    fn rejects_collinear_points() {
+        let line = (0..5).map(|i| Vec2::splat(i as f32)).collect();
+        let part = LevelPartBuilder::new(LevelBiome::Forest).with_points(line);
+        assert!(matches!(
+            validate(part),
+            Err(LevelError::ZeroArea { part: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn rejects_degenerate_outlines() {
+        let bow_tie = [(0.0, 0.0), (10.0, 10.0), (10.0, 0.0), (0.0, 10.0)].map(Vec2::from);
+        let part = LevelPartBuilder::new(LevelBiome::Forest)
+            .with_count(4)
+            .with_region(PartRegion::Polygon(bow_tie.to_vec()));
+        assert!(matches!(
+            validate(part),
+            Err(LevelError::SelfIntersecting { part: 0 })
+        ));
+
+        let flat = [(0.0, 0.0), (5.0, 0.0), (10.0, 0.0)].map(Vec2::from);
+        let part = LevelPartBuilder::new(LevelBiome::Forest)
+            .with_count(4)
+            .with_region(PartRegion::Polygon(flat.to_vec()));
+        assert!(matches!(
+            validate(part),
+            Err(LevelError::ZeroArea { part: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn rejects_too_few_points() {
+        let part = LevelPartBuilder::new(LevelBiome::Forest).with_points(vec![Vec2::ZERO, Vec2::X]);
+        assert!(matches!(
+            validate(part),
+            Err(LevelError::TooFewPoints { part: 0, count: 2 })
+        ));
+    }
+
+    #[test]
+    fn rejects_an_empty_part() {
+        let mut builder = LevelBuilder::new(1);
+        builder.add(
+            Vec2::ZERO,
+            LevelPartBuilder::new(LevelBiome::Forest).with_size(20.0, 20.0),
+        );
+        assert!(matches!(
+            builder.validate(),
+            Err(LevelError::EmptyPart { part: 0 })
+        ));
+    }
+
+    // `small_level` with flat ground and the walls given by `wall` in texels.
+    fn terrain(wall: impl Fn(u32) -> f32) -> Level {
+        let mut level = small_level();
+        let (width, height) = level.height_map.dimensions();
+        level.height_map = ImageBuffer::from_fn(width, height, |x, _| Luma([wall(x)]));
+        level.normal_map = ImageBuffer::from_fn(width, height, |x, y| {
+            normal_at(&level.height_map, level.scale, x, y)
+        });
+        level.slopes = slope_tiles(&level.height_map, level.scale);
+        level
+    }
+
+    // World position of the middle of texel column `x`, in the middle row.
+    fn column(level: &Level, x: u32) -> Vec3 {
+        let width = level.height_map.width() as f32;
+        let pos_x = level.bounds.min.x + (x as f32 + 0.5) / width * level.bounds.width();
+        Vec3::new(pos_x, 0.0, level.bounds.center().y)
+    }
+
+    #[test]
+    fn hits_a_wall_head_on() {
+        let level = terrain(|x| if x >= 30 { 10.0 } else { -1.0 });
+        let origin = column(&level, 10) + Vec3::Y * 2.0;
+
+        let hit = level.raycast(origin, Dir3::X, 50.0).unwrap();
+        // the bilinear height climbs from -1 to 10 between the two columns and reaches 2
+        // three elevenths of the way
+        let face = column(&level, 29).lerp(column(&level, 30), 3.0 / 11.0);
+        assert!(
+            (hit.point.x - face.x).abs() < 0.05,
+            "{} != {}",
+            hit.point.x,
+            face.x
+        );
+        assert!((hit.point.y - 2.0).abs() < 0.05);
+        assert!((hit.point.z - origin.z).abs() < 1e-4);
+        assert!((hit.distance - origin.distance(hit.point)).abs() < 1e-4);
+        assert!(hit.normal.dot(Vec3::NEG_X) > 0.9);
+        assert!(hit.normal.z.abs() < 1e-4);
+
+        assert!(level.raycast(origin, Dir3::NEG_X, 50.0).is_none());
+        assert!(level.raycast(origin, Dir3::X, 10.0).is_none());
+    }
+
+    #[test]
+    fn does_not_tunnel_through_thin_walls() {
+        let level = terrain(|x| if x == 30 { 10.0 } else { -1.0 });
+        let (before, wall) = (column(&level, 29).x, column(&level, 30).x);
+
+        // all of these reach the wall well above the ground
+        for (height, drop) in [(1.0, 0.0), (5.0, 0.1), (9.0, 0.3), (1.0, 0.02), (0.5, -0.2)] {
+            let origin = column(&level, 5) + Vec3::Y * height;
+            let dir = Dir3::new(Vec3::new(1.0, -drop, 0.0)).unwrap();
+            let Some(hit) = level.raycast(origin, dir, 100.0) else {
+                panic!("ray from height {height} went through the wall");
+            };
+            assert!(
+                (before..wall).contains(&hit.point.x),
+                "ray from height {height} hit at {}",
+                hit.point
+            );
+        }
+    }
+
+    #[test]
+    fn line_of_sight_over_a_ridge() {
+        let level = terrain(|x| if (29..=31).contains(&x) { 4.0 } else { -1.0 });
+        let [left, right] = [20, 40].map(|x| column(&level, x));
+
+        for height in [1.0, 3.0] {
+            let (a, b) = (left + Vec3::Y * height, right + Vec3::Y * height);
+            assert!(!level.has_line_of_sight(a, b));
+            assert!(!level.has_line_of_sight(b, a));
+        }
+        let (a, b) = (left + Vec3::Y * 5.0, right + Vec3::Y * 5.0);
+        assert!(level.has_line_of_sight(a, b));
+        assert!(level.has_line_of_sight(b, a));
+        // looking from high up over the ridge down to the foot of it
+        let (a, b) = (left + Vec3::Y * 20.0, right + Vec3::Y * 0.5);
+        assert!(level.has_line_of_sight(a, b));
+        assert!(level.has_line_of_sight(b, a));
+    }
+
+    #[test]
+    fn raycast_many_matches_single_rays() {
+        let level = terrain(|x| if (29..=31).contains(&x) { 4.0 } else { -1.0 });
+        let rays = [
+            (column(&level, 20) + Vec3::Y, Dir3::X),
+            (column(&level, 40) + Vec3::Y * 3.0, Dir3::NEG_X),
+            (column(&level, 20) + Vec3::Y * 5.0, Dir3::X),
+            (column(&level, 25) + Vec3::Y * 5.0, Dir3::NEG_Y),
+            (
+                column(&level, 10) + Vec3::Y * 8.0,
+                Dir3::new(Vec3::new(1.0, -0.3, 0.2)).unwrap(),
+            ),
+        ];
+        let key = |hit: Option<RayHit>| hit.map(|hit| (hit.point, hit.normal, hit.distance));
+
+        let many = level.raycast_many(rays, 40.0);
+        assert_eq!(many.len(), rays.len());
+        assert!(many.iter().filter(|hit| hit.is_some()).count() >= 3);
+        for ((origin, dir), hit) in rays.into_iter().zip(many) {
+            assert_eq!(key(hit), key(level.raycast(origin, dir, 40.0)));
+        }
+    }
+}
@@ -0,0 +1,467 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, ErrorKind},
+    path::Path,
+};
+
+use bevy::prelude::*;
+use imageproc::image::{ImageBuffer, Pixel};
+use kiddo::KdTree;
+use petgraph::{Graph, Undirected, graph::NodeIndex, visit::EdgeRef};
+
+use crate::level::{
+    Level, LevelBiome, LevelPart, PartRegion,
+    gates::GateCondition,
+    hazards::{Hazard, HazardKind, PartHazard},
+    layout::LevelLayout,
+    props::{Prop, PropKind},
+    run::RunRules,
+    slope_tiles,
+};
+
+const MAGIC: [u8; 4] = *b"TWGL";
+// Version of the file format, changes to the generator are covered by the build script's
+// hash of its sources.
+const VERSION: u32 = 7;
+
+fn cache_key(source: &[u8], seed: u64, scale: f32) -> u64 {
+    let mut hash = 0xcbf29ce484222325_u64;
+    for byte in [
+        env!("LEVEL_GENERATOR_HASH").as_bytes(),
+        source,
+        &VERSION.to_le_bytes(),
+        &seed.to_le_bytes(),
+        &scale.to_le_bytes(),
+    ]
+    .concat()
+    {
+        hash = (hash ^ byte as u64).wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+impl LevelLayout {
+    pub fn cache_key(&self, seed: u64, scale: f32) -> u64 {
+        cache_key(self.source.as_bytes(), seed, scale)
+    }
+}
+
+// Rules are keyed by their ron form, any change to them changes the generated run. The
+// floor goes in too, only the last one has the boss.
+impl RunRules {
+    pub fn cache_key(&self, seed: u64, floor: usize, scale: f32) -> u64 {
+        let mut source = ron::to_string(self).unwrap().into_bytes();
+        source.extend((floor as u64).to_le_bytes());
+        cache_key(&source, seed, scale)
+    }
+}
+
+impl Level {
+    pub fn load_or_build(dir: impl AsRef<Path>, key: u64, build: impl FnOnce() -> Level) -> Level {
+        let path = dir.as_ref().join(format!("level-{key:016x}.bin"));
+
+        match Self::load(&path, key) {
+            Ok(level) => return level,
+            Err(err) if err.kind() != ErrorKind::NotFound => {
+                warn!("Ignoring level cache {}: {err}", path.display());
+            }
+            Err(_) => {}
+        }
+
+        let level = build();
+        if let Err(err) = fs::create_dir_all(dir).and_then(|_| level.save(&path, key)) {
+            error!("Failed to write level cache {}: {err}", path.display());
+        }
+        level
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>, key: u64) -> io::Result<()> {
+        let mut writer = Writer(vec![]);
+
+        writer.bytes(&MAGIC);
+        writer.u32(VERSION);
+        writer.u64(key);
+
+        writer.u64(self.seed);
+        writer.f32(self.scale);
+        writer.rect(self.bounds);
+        writer.graph(&self.graph);
+
+        writer.u32(self.parts.len() as u32);
+        for part in &self.parts {
+            writer.u32(part.biome as u32);
+            writer.u32(part.nodes.start as u32);
+            writer.u32(part.nodes.end as u32);
+            writer.rect(part.bounds);
+            writer.f32(part.radius);
+            writer.region(&part.region);
+            writer.f32(part.blend);
+            writer.hazard_kind(part.hazard.map(|hazard| hazard.kind));
+            writer.f32(part.hazard.map_or(0.0, |hazard| hazard.density));
+            writer.u32(part.gate.map_or(0, |gate| gate as u32 + 1));
+            writer.graph(&part.graph);
+        }
+
+        writer.u32(self.props.len() as u32);
+        for prop in &self.props {
+            writer.u32(prop.kind as u32);
+            writer.f32(prop.pos.x);
+            writer.f32(prop.pos.y);
+            writer.f32(prop.scale);
+            writer.f32(prop.rotation);
+            writer.u32(prop.obstacle as u32);
+        }
+
+        writer.u32(self.hazards.len() as u32);
+        for hazard in &self.hazards {
+            writer.hazard_kind(Some(hazard.kind));
+            writer.f32(hazard.pos.x);
+            writer.f32(hazard.pos.y);
+            writer.f32(hazard.radius);
+        }
+
+        writer.u64(self.kd_terrain.size());
+        for (item, [x, y]) in self.kd_terrain.iter() {
+            writer.u64(item);
+            writer.f32(x);
+            writer.f32(y);
+        }
+
+        writer.image(&self.biome_map);
+        writer.image(&self.height_map);
+        writer.image(&self.normal_map);
+
+        fs::write(path, writer.0)
+    }
+
+    pub fn load(path: impl AsRef<Path>, key: u64) -> io::Result<Self> {
+        let data = fs::read(path)?;
+        let mut reader = Reader(&data);
+
+        if reader.bytes(MAGIC.len())? != MAGIC {
+            return Err(invalid_data("not a level cache"));
+        }
+        let version = reader.u32()?;
+        if version != VERSION {
+            return Err(invalid_data(format!(
+                "version {version}, expected {VERSION}"
+            )));
+        }
+        if reader.u64()? != key {
+            return Err(invalid_data("key mismatch"));
+        }
+
+        let seed = reader.u64()?;
+        let scale = reader.f32()?;
+        let bounds = reader.rect()?;
+        let graph = reader.graph()?;
+
+        let mut parts = vec![];
+        for _ in 0..reader.u32()? {
+            let biome = *LevelBiome::ALL
+                .get(reader.u32()? as usize)
+                .ok_or_else(|| invalid_data("unknown biome"))?;
+            let nodes = reader.u32()? as usize..reader.u32()? as usize;
+            let bounds = reader.rect()?;
+            let radius = reader.f32()?;
+            let region = reader.region()?;
+            let blend = reader.f32()?;
+            let hazard_kind = reader.hazard_kind()?;
+            let density = reader.f32()?;
+            let gate = match reader.u32()? {
+                0 => None,
+                gate => Some(
+                    *GateCondition::ALL
+                        .get(gate as usize - 1)
+                        .ok_or_else(|| invalid_data("unknown gate"))?,
+                ),
+            };
+            let graph = reader.graph()?;
+            parts.push(LevelPart {
+                graph,
+                nodes,
+                bounds,
+                radius,
+                biome,
+                region,
+                blend,
+                hazard: hazard_kind.map(|kind| PartHazard { kind, density }),
+                gate,
+            });
+        }
+
+        let mut props = vec![];
+        for _ in 0..reader.u32()? {
+            let kind = *PropKind::ALL
+                .get(reader.u32()? as usize)
+                .ok_or_else(|| invalid_data("unknown prop"))?;
+            props.push(Prop {
+                kind,
+                pos: Vec2::new(reader.f32()?, reader.f32()?),
+                scale: reader.f32()?,
+                rotation: reader.f32()?,
+                obstacle: reader.u32()? != 0,
+            });
+        }
+
+        let mut hazards = vec![];
+        for _ in 0..reader.u32()? {
+            let kind = reader
+                .hazard_kind()?
+                .ok_or_else(|| invalid_data("hazard without a kind"))?;
+            hazards.push(Hazard {
+                kind,
+                pos: Vec2::new(reader.f32()?, reader.f32()?),
+                radius: reader.f32()?,
+            });
+        }
+
+        let mut kd_terrain = KdTree::new();
+        for _ in 0..reader.u64()? {
+            let item = reader.u64()?;
+            let point = [reader.f32()?, reader.f32()?];
+            kd_terrain.add(&point, item);
+        }
+
+        let biome_map = reader.image()?;
+        let height_map = reader.image()?;
+        let normal_map = reader.image()?;
+
+        if !reader.0.is_empty() {
+            return Err(invalid_data("trailing data"));
+        }
+
+        let slopes = slope_tiles(&height_map, scale);
+
+        let mut level = Self {
+            graph,
+            seed,
+            parts,
+            kd_terrain,
+            kd_creatures: Default::default(),
+            creatures: HashMap::new(),
+            flow_fields: HashMap::new(),
+            bounds,
+            scale,
+            biome_map,
+            height_map,
+            normal_map,
+            slopes,
+            props,
+            hazards,
+            kd_hazards: KdTree::new(),
+            edge_hazards: vec![],
+            gates: vec![],
+        };
+        level.index_hazards();
+        // gates only depend on the graph and the parts, and always start closed
+        level.place_gates();
+        Ok(level)
+    }
+}
+
+fn invalid_data(msg: impl Into<String>) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, msg.into())
+}
+
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.0.extend_from_slice(bytes);
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    fn f32(&mut self, value: f32) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    fn rect(&mut self, rect: Rect) {
+        for value in [rect.min.x, rect.min.y, rect.max.x, rect.max.y] {
+            self.f32(value);
+        }
+    }
+
+    fn region(&mut self, region: &PartRegion) {
+        match region {
+            PartRegion::Rect => self.u32(0),
+            PartRegion::Polygon(points) => {
+                self.u32(1);
+                self.u32(points.len() as u32);
+                for point in points {
+                    self.f32(point.x);
+                    self.f32(point.y);
+                }
+            }
+            PartRegion::Circle { radius } => {
+                self.u32(2);
+                self.f32(*radius);
+            }
+            PartRegion::Voronoi => self.u32(3),
+        }
+    }
+
+    fn hazard_kind(&mut self, kind: Option<HazardKind>) {
+        self.u32(kind.map_or(0, |kind| kind as u32 + 1));
+    }
+
+    fn graph(&mut self, graph: &Graph<Vec2, f32, Undirected>) {
+        self.u32(graph.node_count() as u32);
+        for point in graph.node_weights() {
+            self.f32(point.x);
+            self.f32(point.y);
+        }
+        self.u32(graph.edge_count() as u32);
+        for edge in graph.edge_references() {
+            self.u32(edge.source().index() as u32);
+            self.u32(edge.target().index() as u32);
+            self.f32(*edge.weight());
+        }
+    }
+
+    fn image<P: Pixel<Subpixel = f32>>(&mut self, image: &ImageBuffer<P, Vec<f32>>) {
+        self.u32(image.width());
+        self.u32(image.height());
+        for value in image.as_raw() {
+            self.f32(*value);
+        }
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl Reader<'_> {
+    fn bytes(&mut self, len: usize) -> io::Result<&[u8]> {
+        if self.0.len() < len {
+            return Err(ErrorKind::UnexpectedEof.into());
+        }
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+        Ok(self.bytes(N)?.try_into().unwrap())
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+
+    fn f32(&mut self) -> io::Result<f32> {
+        Ok(f32::from_le_bytes(self.array()?))
+    }
+
+    fn rect(&mut self) -> io::Result<Rect> {
+        Ok(Rect {
+            min: Vec2::new(self.f32()?, self.f32()?),
+            max: Vec2::new(self.f32()?, self.f32()?),
+        })
+    }
+
+    fn region(&mut self) -> io::Result<PartRegion> {
+        Ok(match self.u32()? {
+            0 => PartRegion::Rect,
+            1 => {
+                let mut points = vec![];
+                for _ in 0..self.u32()? {
+                    points.push(Vec2::new(self.f32()?, self.f32()?));
+                }
+                PartRegion::Polygon(points)
+            }
+            2 => PartRegion::Circle {
+                radius: self.f32()?,
+            },
+            3 => PartRegion::Voronoi,
+            _ => return Err(invalid_data("unknown region")),
+        })
+    }
+
+    fn hazard_kind(&mut self) -> io::Result<Option<HazardKind>> {
+        match self.u32()? {
+            0 => Ok(None),
+            kind => HazardKind::ALL
+                .get(kind as usize - 1)
+                .copied()
+                .map(Some)
+                .ok_or_else(|| invalid_data("unknown hazard")),
+        }
+    }
+
+    fn graph(&mut self) -> io::Result<Graph<Vec2, f32, Undirected>> {
+        let mut graph = Graph::new_undirected();
+        for _ in 0..self.u32()? {
+            graph.add_node(Vec2::new(self.f32()?, self.f32()?));
+        }
+        for _ in 0..self.u32()? {
+            let source = self.u32()? as usize;
+            let target = self.u32()? as usize;
+            let weight = self.f32()?;
+            if source >= graph.node_count() || target >= graph.node_count() {
+                return Err(invalid_data("edge out of bounds"));
+            }
+            graph.add_edge(NodeIndex::new(source), NodeIndex::new(target), weight);
+        }
+        Ok(graph)
+    }
+
+    fn image<P: Pixel<Subpixel = f32>>(&mut self) -> io::Result<ImageBuffer<P, Vec<f32>>> {
+        let width = self.u32()?;
+        let height = self.u32()?;
+        let len = (width as usize)
+            .checked_mul(height as usize)
+            .and_then(|len| len.checked_mul(P::CHANNEL_COUNT as usize * size_of::<f32>()))
+            .ok_or_else(|| invalid_data("bad image size"))?;
+        let data = self
+            .bytes(len)?
+            .chunks_exact(size_of::<f32>())
+            .map(|bytes| f32::from_le_bytes(bytes.try_into().unwrap()))
+            .collect();
+        ImageBuffer::from_raw(width, height, data).ok_or_else(|| invalid_data("bad image size"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::level::tests::small_level;
+
+    #[test]
+    fn round_trip() {
+        let dir = std::env::temp_dir().join(format!("twg-cache-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("level.bin");
+
+        let level = small_level();
+        level.save(&path, 42).unwrap();
+        let loaded = Level::load(&path, 42);
+        let wrong_key = Level::load(&path, 43);
+        fs::remove_dir_all(&dir).unwrap();
+
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded.seed, level.seed);
+        assert_eq!(loaded.bounds, level.bounds);
+        assert_eq!(
+            loaded.graph.node_weights().collect::<Vec<_>>(),
+            level.graph.node_weights().collect::<Vec<_>>()
+        );
+        assert_eq!(loaded.graph.edge_count(), level.graph.edge_count());
+        assert_eq!(loaded.parts.len(), level.parts.len());
+        assert_eq!(loaded.height_map.as_raw(), level.height_map.as_raw());
+        assert_eq!(loaded.biome_map.as_raw(), level.biome_map.as_raw());
+        assert_eq!(loaded.normal_map.as_raw(), level.normal_map.as_raw());
+        assert!(wrong_key.is_err_and(|err| err.kind() == ErrorKind::InvalidData));
+    }
+}
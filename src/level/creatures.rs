@@ -0,0 +1,162 @@
+use bevy::prelude::*;
+use kiddo::{KdTree, NearestNeighbour, SquaredEuclidean};
+
+use crate::level::Level;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CreatureKind {
+    Player,
+    Enemy,
+}
+
+impl CreatureKind {
+    pub const ALL: [Self; 2] = [Self::Player, Self::Enemy];
+}
+
+impl Level {
+    pub fn insert_creature(&mut self, creature: Entity, kind: CreatureKind, pos: Vec3) {
+        self.remove_creature(creature);
+        let pos = pos.xz();
+        self.kd_creatures[kind as usize].add(&pos.to_array(), creature.to_bits());
+        self.creatures.insert(creature, (kind, pos));
+    }
+
+    pub fn move_creature(&mut self, creature: Entity, pos: Vec3) {
+        let Some((kind, old_pos)) = self.creatures.get_mut(&creature) else {
+            return;
+        };
+        let pos = pos.xz();
+        if *old_pos == pos {
+            return;
+        }
+        let tree = &mut self.kd_creatures[*kind as usize];
+        tree.remove(&old_pos.to_array(), creature.to_bits());
+        tree.add(&pos.to_array(), creature.to_bits());
+        *old_pos = pos;
+    }
+
+    pub fn remove_creature(&mut self, creature: Entity) {
+        if let Some((kind, pos)) = self.creatures.remove(&creature) {
+            self.kd_creatures[kind as usize].remove(&pos.to_array(), creature.to_bits());
+        }
+        self.flow_fields
+            .retain(|(target, _), _| *target != creature);
+    }
+
+    // Forgets every creature and the flow fields towards them, used when the level
+    // stops being the active floor and its entities are despawned.
+    pub fn clear_creatures(&mut self) {
+        self.kd_creatures = Default::default();
+        self.creatures.clear();
+        self.flow_fields.clear();
+    }
+
+    // `kind: None` searches every creature kind, results are sorted by squared distance.
+    pub fn nearest_creatures(
+        &self,
+        count: usize,
+        point: Vec3,
+        kind: Option<CreatureKind>,
+    ) -> Vec<(Entity, f32)> {
+        self.query_creatures(kind, |tree| {
+            tree.nearest_n::<SquaredEuclidean>(&point.xz().to_array(), count)
+        })
+        .take(count)
+        .collect()
+    }
+
+    pub fn creatures_within(
+        &self,
+        point: Vec3,
+        radius: f32,
+        kind: Option<CreatureKind>,
+    ) -> Vec<(Entity, f32)> {
+        self.query_creatures(kind, |tree| {
+            tree.within::<SquaredEuclidean>(&point.xz().to_array(), radius * radius)
+        })
+        .collect()
+    }
+
+    fn query_creatures(
+        &self,
+        kind: Option<CreatureKind>,
+        query: impl Fn(&KdTree<f32, 2>) -> Vec<NearestNeighbour<f32, u64>>,
+    ) -> impl Iterator<Item = (Entity, f32)> {
+        let kinds = match kind {
+            Some(kind) => vec![kind],
+            None => CreatureKind::ALL.to_vec(),
+        };
+
+        let mut neighbours = kinds
+            .into_iter()
+            .flat_map(|kind| query(&self.kd_creatures[kind as usize]))
+            .collect::<Vec<_>>();
+        neighbours.sort_by(|a, b| a.distance.total_cmp(&b.distance));
+
+        neighbours
+            .into_iter()
+            .map(|neighbour| (Entity::from_bits(neighbour.item), neighbour.distance))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::level::tests::small_level;
+
+    fn entities(found: Vec<(Entity, f32)>) -> Vec<Entity> {
+        found.into_iter().map(|(entity, _)| entity).collect()
+    }
+
+    #[test]
+    fn nearest_by_kind() {
+        let mut level = small_level();
+        let [player, near, far] = [1, 2, 3].map(Entity::from_raw);
+        level.insert_creature(player, CreatureKind::Player, Vec3::new(0.0, 5.0, 0.0));
+        level.insert_creature(near, CreatureKind::Enemy, Vec3::new(1.0, 0.0, 0.0));
+        level.insert_creature(far, CreatureKind::Enemy, Vec3::new(0.0, 0.0, 4.0));
+
+        // height doesn't count, the player is right on the point
+        assert_eq!(
+            level.nearest_creatures(3, Vec3::ZERO, None),
+            vec![(player, 0.0), (near, 1.0), (far, 16.0)]
+        );
+        assert_eq!(
+            entities(level.nearest_creatures(1, Vec3::ZERO, Some(CreatureKind::Enemy))),
+            vec![near]
+        );
+        assert_eq!(
+            entities(level.creatures_within(Vec3::ZERO, 2.0, Some(CreatureKind::Enemy))),
+            vec![near]
+        );
+    }
+
+    #[test]
+    fn follows_moves_and_removals() {
+        let mut level = small_level();
+        let [a, b] = [1, 2].map(Entity::from_raw);
+        level.insert_creature(a, CreatureKind::Enemy, Vec3::new(1.0, 0.0, 0.0));
+        level.insert_creature(b, CreatureKind::Enemy, Vec3::new(2.0, 0.0, 0.0));
+
+        level.move_creature(a, Vec3::new(3.0, 0.0, 0.0));
+        assert_eq!(
+            entities(level.nearest_creatures(2, Vec3::ZERO, None)),
+            vec![b, a]
+        );
+
+        // inserting again moves it to the new kind instead of keeping a stale entry
+        level.insert_creature(a, CreatureKind::Player, Vec3::ZERO);
+        assert_eq!(
+            entities(level.creatures_within(Vec3::ZERO, 10.0, Some(CreatureKind::Enemy))),
+            vec![b]
+        );
+
+        level.remove_creature(b);
+        assert_eq!(
+            entities(level.nearest_creatures(2, Vec3::ZERO, None)),
+            vec![a]
+        );
+        level.clear_creatures();
+        assert!(level.nearest_creatures(2, Vec3::ZERO, None).is_empty());
+    }
+}
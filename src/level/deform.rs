@@ -0,0 +1,94 @@
+use bevy::prelude::*;
+use kiddo::SquaredEuclidean;
+use petgraph::graph::NodeIndex;
+
+use crate::level::{Level, normal_at};
+
+impl Level {
+    // Lowers the terrain around `center` by at most `depth`, shaped as a distance field
+    // (`dist - radius`) so the result is still a valid road for `can_walk` and physics.
+    // Returns the changed world area, `None` if the blast was too far from the ground.
+    pub fn carve(&mut self, center: Vec3, radius: f32, depth: f32) -> Option<Rect> {
+        let ground = self.height(center.xz()).max(0.0);
+        if center.y - ground > radius {
+            return None;
+        }
+
+        let min = self
+            .world_to_texture(center.xz() - radius)
+            .floor()
+            .as_uvec2();
+        // the texture size the conversion clamps to can be a pixel or two past the image
+        let (width, height) = self.height_map.dimensions();
+        let max = self
+            .world_to_texture(center.xz() + radius)
+            .ceil()
+            .as_uvec2()
+            .min(UVec2::new(width, height) - 1);
+
+        for y in min.y..=max.y {
+            for x in min.x..=max.x {
+                let pos = self.bounds.min + (UVec2::new(x, y).as_vec2() + 0.5) / self.scale;
+                let dist = pos.distance(center.xz());
+                if dist > radius {
+                    continue;
+                }
+                let height = &mut self.height_map.get_pixel_mut(x, y).0[0];
+                *height = height.min(dist - radius).max(*height - depth);
+            }
+        }
+
+        let min = min.saturating_sub(UVec2::ONE);
+        let max = (max + 1).min(UVec2::new(width, height) - 1);
+        for y in min.y..=max.y {
+            for x in min.x..=max.x {
+                let normal = normal_at(&self.height_map, self.scale, x, y);
+                self.normal_map.put_pixel(x, y, normal);
+            }
+        }
+
+        // the crater itself has a slope of 1 and `max`/`min` never steepen the rest
+        self.raise_slope(min, max, std::f32::consts::SQRT_2);
+
+        self.connect_nodes(center.xz(), radius);
+
+        Some(Rect {
+            min: self.bounds.min + min.as_vec2() / self.scale,
+            max: self.bounds.min + (max + 1).as_vec2() / self.scale,
+        })
+    }
+
+    // Links graph nodes around a fresh crater that can now walk to each other,
+    // so flow fields route through blasted shortcuts.
+    fn connect_nodes(&mut self, center: Vec2, radius: f32) {
+        let reach = 3.0 * radius;
+        let mut nodes = self
+            .kd_terrain
+            .within::<SquaredEuclidean>(&center.to_array(), reach * reach)
+            .into_iter()
+            .map(|neighbour| neighbour.item as usize)
+            .collect::<Vec<_>>();
+        nodes.sort_unstable();
+        nodes.dedup();
+
+        let mut added = false;
+        for (idx, &a) in nodes.iter().enumerate() {
+            for &b in &nodes[idx + 1..] {
+                let (a, b) = (NodeIndex::new(a), NodeIndex::new(b));
+                if self.graph.contains_edge(a, b) {
+                    continue;
+                }
+                let (pos_a, pos_b) = (self.graph[a], self.graph[b]);
+                if self.can_walk(pos_a, pos_b, 0.5) && self.can_walk(pos_b, pos_a, 0.5) {
+                    self.graph.add_edge(a, b, pos_a.distance(pos_b));
+                    added = true;
+                }
+            }
+        }
+
+        if added {
+            self.index_hazards();
+            self.flow_fields.clear();
+        }
+    }
+}
@@ -0,0 +1,157 @@
+use bevy::prelude::*;
+use petgraph::visit::EdgeRef;
+use serde::{Deserialize, Serialize};
+
+use crate::level::Level;
+
+// What opens the gates of a part, the game decides when it's met.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Deserialize, Serialize)]
+pub enum GateCondition {
+    // Every enemy spawned in the part is dead.
+    Clear,
+    // The key lying somewhere in the part is picked up.
+    Key,
+    // The lever in the part is pulled.
+    Lever,
+}
+
+impl GateCondition {
+    pub const ALL: [Self; 3] = [Self::Clear, Self::Key, Self::Lever];
+}
+
+// A wall across a road leaving a gated part, laid over the height map while closed.
+#[derive(Clone, Copy, Debug)]
+pub struct Gate {
+    pub condition: GateCondition,
+    // The part the gate leads out of, its condition opens the gate.
+    pub part: usize,
+    pub edge: usize,
+    pub pos: Vec2,
+    // Along the road, the wall is perpendicular to it.
+    pub dir: Vec2,
+    pub half_width: f32,
+    pub open: bool,
+}
+
+impl Gate {
+    pub const THICKNESS: f32 = 0.5;
+    // How far the wall reaches past the road edges into the walls.
+    const WALL_MARGIN: f32 = 1.5;
+    // Beyond this distance a gate can't be above a road, so it's skipped.
+    const REACH: f32 = 10.0;
+
+    pub fn segment(&self) -> (Vec2, Vec2) {
+        let side = self.dir.perp() * self.half_width;
+        (self.pos - side, self.pos + side)
+    }
+
+    // Area of the height map the wall is laid over.
+    pub fn bounds(&self) -> Rect {
+        let (a, b) = self.segment();
+        Rect::from_corners(a, b).inflate(Self::THICKNESS)
+    }
+
+    // Same distance field as the roads, positive inside the wall.
+    fn height(&self, point: Vec2) -> (f32, Vec2) {
+        let (a, b) = self.segment();
+        let t = (point - a).dot(b - a) / (b - a).length_squared();
+        let closest = a.lerp(b, t.clamp(0.0, 1.0));
+        let away = (point - closest).try_normalize().unwrap_or(self.dir);
+        (Self::THICKNESS - point.distance(closest), away)
+    }
+}
+
+impl Level {
+    pub fn gates(&self) -> &[Gate] {
+        &self.gates
+    }
+
+    // Opens every gate of `part`, returns the ones that were closed.
+    pub fn open_gates(&mut self, part: usize) -> Vec<usize> {
+        let opened = self
+            .gates
+            .iter_mut()
+            .enumerate()
+            .filter(|(_, gate)| gate.part == part && !gate.open)
+            .map(|(idx, gate)| {
+                gate.open = true;
+                idx
+            })
+            .collect::<Vec<_>>();
+        if !opened.is_empty() {
+            self.flow_fields.clear();
+        }
+        opened
+    }
+
+    pub fn part_at(&self, world_pos: Vec2) -> Option<usize> {
+        let node = self.nearest_id_terrain(1, world_pos)[0].index();
+        self.parts
+            .iter()
+            .position(|part| part.nodes.contains(&node))
+    }
+
+    // Height of the highest closed gate at `world_pos` and the direction away from it.
+    pub(super) fn gate_height(&self, world_pos: Vec2) -> Option<(f32, Vec2)> {
+        self.gates
+            .iter()
+            .filter(|gate| !gate.open && gate.pos.distance(world_pos) < Gate::REACH)
+            .map(|gate| gate.height(world_pos))
+            .max_by(|(a, _), (b, _)| a.total_cmp(b))
+    }
+
+    pub(super) fn gate_blocks(&self, edge: usize) -> bool {
+        self.gates
+            .iter()
+            .any(|gate| gate.edge == edge && !gate.open)
+    }
+
+    // Every road between a gated part and a part added after it gets a gate halfway.
+    pub(super) fn place_gates(&mut self) {
+        let node_part = |node: usize| {
+            self.parts
+                .iter()
+                .position(|part| part.nodes.contains(&node))
+        };
+
+        let mut gates = vec![];
+        for edge in self.graph.edge_references() {
+            let (Some(source), Some(target)) = (
+                node_part(edge.source().index()),
+                node_part(edge.target().index()),
+            ) else {
+                continue;
+            };
+            let part = source.min(target);
+            let Some(condition) = self.parts[part].gate.filter(|_| source != target) else {
+                continue;
+            };
+
+            let (a, b) = (self.graph[edge.source()], self.graph[edge.target()]);
+            let pos = a.lerp(b, 0.5);
+            gates.push(Gate {
+                condition,
+                part,
+                edge: edge.id().index(),
+                pos,
+                dir: (b - a).normalize_or(Vec2::Y),
+                half_width: (-self.height(pos)).max(0.0) + Gate::WALL_MARGIN,
+                open: false,
+            });
+        }
+
+        for gate in &gates {
+            // the wall sides have a slope of 1, same as the obstacles
+            let min = self
+                .world_to_texture(gate.pos - Gate::REACH)
+                .floor()
+                .as_uvec2();
+            let max = self
+                .world_to_texture(gate.pos + Gate::REACH)
+                .ceil()
+                .as_uvec2();
+            self.raise_slope(min, max, std::f32::consts::SQRT_2);
+        }
+        self.gates = gates;
+    }
+}
@@ -0,0 +1,185 @@
+use bevy::prelude::*;
+use kiddo::{KdTree, SquaredEuclidean};
+use petgraph::visit::EdgeRef;
+use rand::{Rng, SeedableRng, rngs::StdRng, seq::IndexedRandom};
+use serde::Deserialize;
+
+use crate::level::{Level, LevelBiome};
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Deserialize)]
+pub enum HazardKind {
+    Acid,
+    Mud,
+    Spores,
+}
+
+impl HazardKind {
+    pub const ALL: [Self; 3] = [Self::Acid, Self::Mud, Self::Spores];
+
+    pub fn damage_per_second(&self) -> f32 {
+        match self {
+            Self::Acid => 20.0,
+            Self::Mud => 0.0,
+            Self::Spores => 8.0,
+        }
+    }
+
+    // Multiplies the speed of creatures standing in the zone.
+    pub fn speed_factor(&self) -> f32 {
+        match self {
+            Self::Acid => 0.85,
+            Self::Mud => 0.4,
+            Self::Spores => 1.0,
+        }
+    }
+
+    fn bit(&self) -> u8 {
+        1 << *self as u8
+    }
+}
+
+// What a part scatters over its roads, `density` is the share of the part edges
+// that get a zone.
+#[derive(Clone, Copy, Debug, Deserialize)]
+pub struct PartHazard {
+    pub kind: HazardKind,
+    pub density: f32,
+}
+
+impl LevelBiome {
+    pub fn hazard(&self) -> Option<PartHazard> {
+        let kind = match self {
+            Self::Meat => HazardKind::Acid,
+            Self::Forest => HazardKind::Mud,
+            Self::Mushroom => HazardKind::Spores,
+            Self::Safe | Self::Home | Self::Cave | Self::Temple | Self::Boss => return None,
+        };
+        Some(PartHazard {
+            kind,
+            density: 0.15,
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Hazard {
+    pub kind: HazardKind,
+    pub pos: Vec2,
+    pub radius: f32,
+}
+
+impl Hazard {
+    pub const MAX_RADIUS: f32 = 5.0;
+    // Extra path cost for every metre of road inside a zone.
+    const PATH_PENALTY: f32 = 10.0;
+    // Step of the samples along roads when measuring how much of them is covered.
+    const SAMPLE_STEP: f32 = 0.5;
+}
+
+impl Level {
+    pub fn hazards(&self) -> &[Hazard] {
+        &self.hazards
+    }
+
+    pub fn hazard(&self, world_pos: Vec2) -> Option<HazardKind> {
+        self.kd_hazards
+            .within_unsorted::<SquaredEuclidean>(
+                &world_pos.to_array(),
+                Hazard::MAX_RADIUS * Hazard::MAX_RADIUS,
+            )
+            .into_iter()
+            .map(|neighbour| &self.hazards[neighbour.item as usize])
+            .find(|hazard| hazard.pos.distance_squared(world_pos) <= hazard.radius * hazard.radius)
+            .map(|hazard| hazard.kind)
+    }
+
+    // True if walking from `from` to `to` steps into a zone the walker isn't immune to.
+    // Zones around `from` are ignored, so creatures already standing in one can leave.
+    pub fn crosses_hazard(&self, from: Vec2, to: Vec2, immune: &[HazardKind]) -> bool {
+        let start = self.hazard(from);
+        let steps = (from.distance(to) / Hazard::SAMPLE_STEP).ceil() as usize;
+        (1..=steps)
+            .filter_map(|step| self.hazard(from.lerp(to, step as f32 / steps as f32)))
+            .any(|kind| Some(kind) != start && !immune.contains(&kind))
+    }
+
+    // Edge cost for flow fields, roads through zones cost more unless the walker is immune.
+    pub(super) fn path_cost(&self, edge: usize, length: f32, immune: u8) -> f32 {
+        let covered = self.edge_hazards.get(edge).map_or(0.0, |covered| {
+            HazardKind::ALL
+                .iter()
+                .filter(|kind| immune & kind.bit() == 0)
+                .map(|kind| covered[*kind as usize])
+                .sum()
+        });
+        length + covered * Hazard::PATH_PENALTY
+    }
+
+    pub(super) fn immunity_mask(immune: &[HazardKind]) -> u8 {
+        immune.iter().fold(0, |mask, kind| mask | kind.bit())
+    }
+
+    // Zones sit on random roads of each part that declares a hazard.
+    pub(super) fn place_hazards(&mut self) {
+        let mut hazards = vec![];
+
+        for (idx, part) in self.parts.iter().enumerate() {
+            let Some(hazard) = part.hazard else {
+                continue;
+            };
+
+            let seed = self.seed ^ (idx as u64 + 1).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+            let mut rng = StdRng::seed_from_u64(seed);
+
+            let edges = self
+                .graph
+                .edge_references()
+                .filter(|edge| {
+                    part.nodes.contains(&edge.source().index())
+                        && part.nodes.contains(&edge.target().index())
+                })
+                .map(|edge| (self.graph[edge.source()], self.graph[edge.target()]))
+                .collect::<Vec<_>>();
+
+            let count = (hazard.density * edges.len() as f32).round() as usize;
+            for &(a, b) in edges.choose_multiple(&mut rng, count) {
+                hazards.push(Hazard {
+                    kind: hazard.kind,
+                    pos: a.lerp(b, rng.random_range(0.25..0.75)),
+                    radius: rng.random_range(2.5..=Hazard::MAX_RADIUS),
+                });
+            }
+        }
+
+        self.hazards = hazards;
+        self.index_hazards();
+    }
+
+    // Rebuilds the lookup tree and the covered length of every road, needed again
+    // whenever the graph gets new edges.
+    pub(super) fn index_hazards(&mut self) {
+        self.kd_hazards = KdTree::new();
+        for (idx, hazard) in self.hazards.iter().enumerate() {
+            self.kd_hazards.add(&hazard.pos.to_array(), idx as u64);
+        }
+
+        self.edge_hazards = self
+            .graph
+            .edge_references()
+            .map(|edge| {
+                let (a, b) = (self.graph[edge.source()], self.graph[edge.target()]);
+                let steps = (a.distance(b) / Hazard::SAMPLE_STEP).ceil().max(1.0) as usize;
+                let step_len = a.distance(b) / steps as f32;
+
+                let mut covered = [0.0; HazardKind::ALL.len()];
+                for step in 0..steps {
+                    let point = a.lerp(b, (step as f32 + 0.5) / steps as f32);
+                    if let Some(kind) = self.hazard(point) {
+                        covered[kind as usize] += step_len;
+                    }
+                }
+                covered
+            })
+            .collect();
+    }
+}
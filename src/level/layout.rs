@@ -0,0 +1,239 @@
+use std::{collections::HashMap, fmt, fs, io};
+
+use bevy::{
+    asset::{
+        AssetLoader, LoadContext,
+        io::{Reader, file::FileAssetReader},
+    },
+    prelude::*,
+};
+use ron::error::SpannedError;
+use serde::{
+    Deserialize, Deserializer,
+    de::{self, SeqAccess, Visitor},
+};
+
+use crate::level::{
+    LevelBiome, LevelBuilder, LevelPartBuilder, PartAlign, PartRegion, gates::GateCondition,
+    hazards::PartHazard,
+};
+
+pub const DEFAULT_PATH: &str = "levels/world.layout.ron";
+
+#[derive(Asset, TypePath, Deserialize)]
+pub struct LevelLayout {
+    #[serde(deserialize_with = "parts")]
+    parts: Vec<PartLayout>,
+    #[serde(skip)]
+    pub(super) source: String,
+}
+
+#[derive(Deserialize)]
+struct PartLayout {
+    id: String,
+    biome: LevelBiome,
+    size: Vec2,
+    count: usize,
+    fill_ratio: f32,
+    #[serde(default)]
+    points: Option<Vec<Vec2>>,
+    #[serde(default)]
+    after: Option<PartAnchor>,
+    #[serde(default)]
+    offset: Vec2,
+    #[serde(default)]
+    region: PartRegion,
+    #[serde(default)]
+    blend: Option<f32>,
+    // Overrides the biome's hazard, a zero density keeps the roads clear.
+    #[serde(default)]
+    hazard: Option<PartHazard>,
+    #[serde(default)]
+    gate: Option<GateCondition>,
+}
+
+#[derive(Deserialize)]
+struct PartAnchor {
+    part: String,
+    align: PartAlign,
+}
+
+// Parts may only be placed after the ones above them. Checked while parsing, so ron
+// reports the position of the offending part.
+fn parts<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<PartLayout>, D::Error> {
+    struct PartsVisitor;
+
+    impl<'de> Visitor<'de> for PartsVisitor {
+        type Value = Vec<PartLayout>;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "a list of parts")
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+            let mut parts = Vec::<PartLayout>::new();
+            while let Some(part) = seq.next_element::<PartLayout>()? {
+                if let Some(after) = &part.after
+                    && !parts.iter().any(|other| other.id == after.part)
+                {
+                    return Err(de::Error::custom(format!(
+                        "part \"{}\" is not defined before this point",
+                        after.part
+                    )));
+                }
+                if parts.iter().any(|other| other.id == part.id) {
+                    return Err(de::Error::custom(format!(
+                        "part \"{}\" is already defined",
+                        part.id
+                    )));
+                }
+                parts.push(part);
+            }
+            Ok(parts)
+        }
+    }
+
+    deserializer.deserialize_seq(PartsVisitor)
+}
+
+#[derive(Debug)]
+pub enum LayoutError {
+    Io(io::Error),
+    Parse(SpannedError),
+}
+
+impl fmt::Display for LayoutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "{err}"),
+            Self::Parse(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl std::error::Error for LayoutError {}
+
+impl LevelLayout {
+    pub fn parse(bytes: &[u8]) -> Result<Self, LayoutError> {
+        let mut layout = ron::de::from_bytes::<Self>(bytes).map_err(LayoutError::Parse)?;
+        layout.source = String::from_utf8_lossy(bytes).into_owned();
+        Ok(layout)
+    }
+
+    // The level is built before the game's app exists, so the file is read straight from
+    // the asset folder. `path` is relative to that folder.
+    pub fn load(path: &str) -> Result<Self, LayoutError> {
+        let root = FileAssetReader::new(AssetPlugin::default().file_path);
+        let bytes = fs::read(root.root_path().join(path)).map_err(LayoutError::Io)?;
+        Self::parse(&bytes)
+    }
+}
+
+#[derive(Default)]
+pub struct LevelLayoutLoader;
+
+impl AssetLoader for LevelLayoutLoader {
+    type Asset = LevelLayout;
+    type Settings = ();
+    type Error = LayoutError;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &(),
+        _load_context: &mut LoadContext<'_>,
+    ) -> Result<LevelLayout, LayoutError> {
+        let mut bytes = vec![];
+        reader
+            .read_to_end(&mut bytes)
+            .await
+            .map_err(LayoutError::Io)?;
+        LevelLayout::parse(&bytes)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["layout.ron"]
+    }
+}
+
+impl LevelBuilder {
+    pub fn from_layout(seed: u64, layout: &LevelLayout) -> Self {
+        let mut builder = Self::new(seed);
+        let mut ids = HashMap::new();
+
+        for part in &layout.parts {
+            let mut part_builder = LevelPartBuilder::new(part.biome)
+                .with_size(part.size.x, part.size.y)
+                .with_count(part.count)
+                .with_fill_ratio(part.fill_ratio)
+                .with_region(part.region.clone());
+            if let Some(points) = &part.points {
+                part_builder = part_builder.with_points(points.clone());
+            }
+            if let Some(blend) = part.blend {
+                part_builder = part_builder.with_blend(blend);
+            }
+            if let Some(hazard) = part.hazard {
+                part_builder = part_builder.with_hazard(Some(hazard));
+            }
+            if let Some(gate) = part.gate {
+                part_builder = part_builder.with_gate(gate);
+            }
+
+            let idx = match &part.after {
+                Some(after) => builder.add_after(ids[&after.part], after.align, part_builder),
+                None => builder.add(part.offset, part_builder),
+            };
+            ids.insert(&part.id, idx);
+        }
+
+        builder
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn part(id: &str, after: Option<&str>) -> String {
+        let after = after.map_or(String::new(), |after| {
+            format!("after: Some((part: \"{after}\", align: Down)),")
+        });
+        format!(
+            "(id: \"{id}\", biome: Forest, size: (10.0, 10.0), count: 3, fill_ratio: 0.2, {after})"
+        )
+    }
+
+    fn parse(parts: &[String]) -> Result<LevelLayout, LayoutError> {
+        LevelLayout::parse(format!("(parts: [\n{}\n])", parts.join(",\n")).as_bytes())
+    }
+
+    #[test]
+    fn parses_the_world() {
+        let source = include_str!("../../assets/levels/world.layout.ron");
+        let layout = LevelLayout::parse(source.as_bytes()).unwrap();
+        assert!(!layout.parts.is_empty());
+        assert_eq!(layout.source, source);
+    }
+
+    #[test]
+    fn unknown_part() {
+        let Err(LayoutError::Parse(err)) = parse(&[part("a", None), part("b", Some("c"))]) else {
+            panic!("layout with an unknown part parsed");
+        };
+        // ron points past the comma after the failing element
+        assert_eq!(err.position.line, 4);
+        assert!(err.to_string().contains("\"c\" is not defined"));
+    }
+
+    #[test]
+    fn duplicate_part() {
+        let Err(LayoutError::Parse(err)) =
+            parse(&[part("a", None), part("b", Some("a")), part("a", Some("b"))])
+        else {
+            panic!("layout with a duplicate part parsed");
+        };
+        assert_eq!(err.position.line, 5);
+        assert!(err.to_string().contains("\"a\" is already defined"));
+    }
+}
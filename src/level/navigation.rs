@@ -0,0 +1,153 @@
+use bevy::prelude::*;
+use petgraph::{
+    Graph, Undirected,
+    algo::{astar, dijkstra},
+    graph::{EdgeReference, NodeIndex},
+    visit::{EdgeFiltered, EdgeRef},
+};
+
+use crate::level::{Level, hazards::HazardKind};
+
+pub struct FlowField {
+    target: NodeIndex,
+    next: Vec<Option<NodeIndex>>,
+}
+
+impl FlowField {
+    pub fn new(
+        graph: &Graph<Vec2, f32, Undirected>,
+        target: NodeIndex,
+        cost: impl Fn(EdgeReference<f32>) -> Option<f32>,
+    ) -> Self {
+        // `None` cost marks a road that can't be walked at all
+        let open = EdgeFiltered::from_fn(graph, |edge| cost(edge).is_some());
+        let dist = dijkstra(&open, target, None, |edge| cost(edge).unwrap());
+
+        let next = graph
+            .node_indices()
+            .map(|node| {
+                graph
+                    .edges(node)
+                    .filter_map(|edge| {
+                        let neighbour = edge.target();
+                        Some((neighbour, dist.get(&neighbour)? + cost(edge)?))
+                    })
+                    .min_by(|(_, a), (_, b)| a.total_cmp(b))
+                    .map(|(neighbour, _)| neighbour)
+                    .filter(|_| node != target && dist.contains_key(&node))
+            })
+            .collect();
+
+        Self { target, next }
+    }
+
+    pub fn target(&self) -> NodeIndex {
+        self.target
+    }
+
+    pub fn next(&self, node: NodeIndex) -> Option<NodeIndex> {
+        self.next[node.index()]
+    }
+
+    pub fn reaches(&self, node: NodeIndex) -> bool {
+        node == self.target || self.next(node).is_some()
+    }
+
+    // Nodes from `from` (included) towards the target, empty if the target is unreachable.
+    pub fn path(&self, from: NodeIndex) -> impl Iterator<Item = NodeIndex> {
+        let start = self.reaches(from).then_some(from);
+        std::iter::successors(start, |node| self.next(*node))
+    }
+}
+
+impl Level {
+    // Recomputes the field only when `target` moves to another graph node. Walkers
+    // with different immunities get separate fields, roads through other hazards cost more
+    // and roads behind closed gates are left out.
+    pub fn update_flow_field(&mut self, target: Entity, target_pos: Vec2, immune: &[HazardKind]) {
+        let key = (target, Self::immunity_mask(immune));
+        let node = self.nearest_id_terrain(1, target_pos)[0];
+        if self
+            .flow_fields
+            .get(&key)
+            .is_some_and(|field| field.target == node)
+        {
+            return;
+        }
+        let field = FlowField::new(&self.graph, node, |edge| {
+            let edge_idx = edge.id().index();
+            (!self.gate_blocks(edge_idx)).then(|| self.path_cost(edge_idx, *edge.weight(), key.1))
+        });
+        self.flow_fields.insert(key, field);
+    }
+
+    pub fn flow_field(&self, target: Entity, immune: &[HazardKind]) -> Option<&FlowField> {
+        self.flow_fields.get(&(target, Self::immunity_mask(immune)))
+    }
+
+    // One-off path for walkers that head to a point rather than a creature, with the same
+    // costs as the flow fields. Empty if `to` can't be reached.
+    pub fn find_path(&self, from: Vec2, to: Vec2, immune: &[HazardKind]) -> Vec<NodeIndex> {
+        let mask = Self::immunity_mask(immune);
+        let start = self.nearest_id_terrain(1, from)[0];
+        let goal = self.nearest_id_terrain(1, to)[0];
+        let open = EdgeFiltered::from_fn(&self.graph, |edge| !self.gate_blocks(edge.id().index()));
+        astar(
+            &open,
+            start,
+            |node| node == goal,
+            |edge| self.path_cost(edge.id().index(), *edge.weight(), mask),
+            |node| self.graph[node].distance(self.graph[goal]),
+        )
+        .map(|(_, path)| path)
+        .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 0 - 1 - 2 - 3 with a shortcut 0 - 3 and node 4 cut off behind a closed road.
+    fn graph() -> Graph<Vec2, f32, Undirected> {
+        let mut graph = Graph::new_undirected();
+        let nodes = [(0.0, 0.0), (1.0, 0.0), (2.0, 0.0), (3.0, 0.0), (4.0, 0.0)]
+            .map(|(x, y)| graph.add_node(Vec2::new(x, y)));
+        graph.add_edge(nodes[0], nodes[1], 1.0);
+        graph.add_edge(nodes[1], nodes[2], 1.0);
+        graph.add_edge(nodes[2], nodes[3], 1.0);
+        graph.add_edge(nodes[0], nodes[3], 10.0);
+        graph.add_edge(nodes[3], nodes[4], f32::INFINITY);
+        graph
+    }
+
+    fn cost(edge: EdgeReference<f32>) -> Option<f32> {
+        edge.weight().is_finite().then_some(*edge.weight())
+    }
+
+    #[test]
+    fn reaches_the_target() {
+        let graph = graph();
+        let field = FlowField::new(&graph, NodeIndex::new(3), cost);
+
+        for node in 0..4 {
+            let path = field.path(NodeIndex::new(node)).collect::<Vec<_>>();
+            assert_eq!(path.first(), Some(&NodeIndex::new(node)));
+            assert_eq!(path.last(), Some(&NodeIndex::new(3)));
+        }
+        // the long shortcut loses to the road through 1 and 2
+        assert_eq!(
+            field.path(NodeIndex::new(0)).collect::<Vec<_>>(),
+            [0, 1, 2, 3].map(NodeIndex::new)
+        );
+    }
+
+    #[test]
+    fn closed_roads_cut_off() {
+        let graph = graph();
+        let field = FlowField::new(&graph, NodeIndex::new(0), cost);
+
+        assert!(!field.reaches(NodeIndex::new(4)));
+        assert_eq!(field.path(NodeIndex::new(4)).count(), 0);
+    }
+}
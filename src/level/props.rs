@@ -0,0 +1,190 @@
+use std::f32::consts::TAU;
+
+use bevy::prelude::*;
+use fast_poisson::Poisson2D;
+use kiddo::SquaredEuclidean;
+use rand::{Rng, SeedableRng, rngs::StdRng, seq::IndexedRandom};
+
+use crate::level::{Level, LevelBiome, normal_at};
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum PropKind {
+    Tree,
+    Rock,
+    Stalagmite,
+    Fungus,
+    Pillar,
+    Bones,
+}
+
+impl PropKind {
+    pub const ALL: [Self; 6] = [
+        Self::Tree,
+        Self::Rock,
+        Self::Stalagmite,
+        Self::Fungus,
+        Self::Pillar,
+        Self::Bones,
+    ];
+
+    // Collision radius at scale 1, the models are built to match it.
+    pub fn radius(&self) -> f32 {
+        match self {
+            Self::Tree => 0.6,
+            Self::Rock => 1.2,
+            Self::Stalagmite => 1.0,
+            Self::Fungus => 0.6,
+            Self::Pillar => 1.0,
+            Self::Bones => 0.8,
+        }
+    }
+}
+
+impl LevelBiome {
+    // Props scattered off the roads of the biome with their relative weights.
+    pub fn props(&self) -> &'static [(PropKind, f32)] {
+        match self {
+            Self::Forest => &[(PropKind::Tree, 3.0), (PropKind::Rock, 1.0)],
+            Self::Cave => &[(PropKind::Stalagmite, 2.0), (PropKind::Rock, 1.0)],
+            Self::Mushroom => &[(PropKind::Fungus, 1.0)],
+            Self::Temple => &[(PropKind::Pillar, 1.0)],
+            Self::Meat => &[(PropKind::Bones, 1.0)],
+            Self::Safe | Self::Home | Self::Boss => &[],
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Prop {
+    pub kind: PropKind,
+    pub pos: Vec2,
+    pub scale: f32,
+    pub rotation: f32,
+    // Reaches into walkable space and is stamped into the height map.
+    pub obstacle: bool,
+}
+
+impl Prop {
+    // Poisson spacing between props of a part.
+    pub const SPACING: f32 = 7.0;
+    // Free space kept between a prop and the road centre line.
+    const ROAD_CLEARANCE: f32 = 1.5;
+    // How far around an obstacle the road distance field is updated, enough for any creature.
+    const STAMP_MARGIN: f32 = 2.0;
+
+    pub fn radius(&self) -> f32 {
+        self.kind.radius() * self.scale
+    }
+}
+
+impl Level {
+    pub fn props(&self) -> &[Prop] {
+        &self.props
+    }
+
+    // Poisson samples each part outside its roads. A prop may reach into the road by at
+    // most half its radius, and those that do become obstacles in the height map.
+    pub(super) fn scatter_props(&mut self) {
+        let mut props = vec![];
+
+        for (idx, part) in self.parts.iter().enumerate() {
+            let table = part.biome.props();
+            if table.is_empty() {
+                continue;
+            }
+
+            let seed = self.seed ^ (idx as u64 + 1).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+            let mut rng = StdRng::seed_from_u64(seed);
+            let bounds = part.bounds;
+
+            let points = Poisson2D::new()
+                .with_dimensions(
+                    [bounds.width() as f64, bounds.height() as f64],
+                    Prop::SPACING as f64,
+                )
+                .with_seed(seed)
+                .iter()
+                .map(|[x, y]| bounds.min + Vec2::new(x as f32, y as f32))
+                .collect::<Vec<_>>();
+
+            for pos in points {
+                let Some(&(kind, _)) = table.choose_weighted(&mut rng, |(_, weight)| *weight).ok()
+                else {
+                    continue;
+                };
+                let prop = Prop {
+                    kind,
+                    pos,
+                    scale: rng.random_range(0.8..1.3),
+                    rotation: rng.random_range(0.0..TAU),
+                    obstacle: false,
+                };
+
+                let nearest = self
+                    .kd_terrain
+                    .nearest_one::<SquaredEuclidean>(&pos.to_array());
+                let clearance = prop.radius() + Prop::ROAD_CLEARANCE;
+                if !part.nodes.contains(&(nearest.item as usize))
+                    || nearest.distance < clearance * clearance
+                {
+                    continue;
+                }
+
+                let ring = |radius: f32| {
+                    (0..8).map(move |step| {
+                        let angle = step as f32 * TAU / 8.0;
+                        pos + Vec2::from_angle(angle) * radius
+                    })
+                };
+                let off_road = |point: Vec2| self.height(point) >= 0.0;
+                if !off_road(pos) || !ring(0.5 * prop.radius()).all(off_road) {
+                    continue;
+                }
+
+                props.push(Prop {
+                    obstacle: !ring(prop.radius()).all(off_road),
+                    ..prop
+                });
+            }
+        }
+
+        for prop in props.iter().filter(|prop| prop.obstacle) {
+            self.stamp_obstacle(prop.pos, prop.radius());
+        }
+        self.props = props;
+    }
+
+    // Unions a disc of `radius` around `center` with the walls: `radius - dist` is the
+    // disc's own distance field, so `can_walk`, physics and raycasts all see the obstacle.
+    fn stamp_obstacle(&mut self, center: Vec2, radius: f32) {
+        let reach = radius + Prop::STAMP_MARGIN;
+        let min = self.world_to_texture(center - reach).floor().as_uvec2();
+        // the texture size the conversion clamps to can be a pixel or two past the image
+        let (width, height) = self.height_map.dimensions();
+        let max = self
+            .world_to_texture(center + reach)
+            .ceil()
+            .as_uvec2()
+            .min(UVec2::new(width, height) - 1);
+
+        for y in min.y..=max.y {
+            for x in min.x..=max.x {
+                let pos = self.bounds.min + (UVec2::new(x, y).as_vec2() + 0.5) / self.scale;
+                let height = &mut self.height_map.get_pixel_mut(x, y).0[0];
+                *height = height.max(radius - pos.distance(center));
+            }
+        }
+
+        let min = min.saturating_sub(UVec2::ONE);
+        let max = (max + 1).min(UVec2::new(width, height) - 1);
+        for y in min.y..=max.y {
+            for x in min.x..=max.x {
+                let normal = normal_at(&self.height_map, self.scale, x, y);
+                self.normal_map.put_pixel(x, y, normal);
+            }
+        }
+
+        // the cone has a slope of 1, same as the craters in `carve`
+        self.raise_slope(min, max, std::f32::consts::SQRT_2);
+    }
+}
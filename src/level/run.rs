@@ -0,0 +1,217 @@
+use std::ops::RangeInclusive;
+
+use bevy::prelude::*;
+use rand::{Rng, SeedableRng, rngs::StdRng, seq::IndexedRandom};
+use serde::Serialize;
+
+use crate::level::{
+    LevelBiome, LevelBuilder, LevelPartBuilder, PartAlign, PartRegion, gates::GateCondition,
+};
+
+// Mixed into the seed for the layout rolls, the builder draws from the seed itself.
+const LAYOUT_STREAM: u64 = 0xd1b5_4a32_d192_ed03;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct RunRules {
+    // Number of combat parts on the main path, safe, home and boss parts are added on top.
+    pub stages: RangeInclusive<usize>,
+    pub combat_biomes: Vec<LevelBiome>,
+    pub side_biomes: Vec<LevelBiome>,
+    pub side_chance: f64,
+    pub combat_per_safe: usize,
+    // Each floor is a separate level, only the last one ends with the boss.
+    pub floors: usize,
+    // Chance for a combat part to lock its way out behind a gate.
+    pub gate_chance: f64,
+    pub gate_conditions: Vec<GateCondition>,
+}
+
+impl Default for RunRules {
+    fn default() -> Self {
+        Self {
+            stages: 2..=5,
+            combat_biomes: vec![LevelBiome::Forest, LevelBiome::Cave, LevelBiome::Temple],
+            side_biomes: vec![LevelBiome::Mushroom, LevelBiome::Meat],
+            side_chance: 0.5,
+            combat_per_safe: 2,
+            floors: 3,
+            gate_chance: 0.5,
+            gate_conditions: GateCondition::ALL.to_vec(),
+        }
+    }
+}
+
+impl RunRules {
+    pub fn floor_seed(&self, seed: u64, floor: usize) -> u64 {
+        seed ^ (floor as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15)
+    }
+
+    pub fn is_last_floor(&self, floor: usize) -> bool {
+        floor + 1 >= self.floors
+    }
+}
+
+fn home_part() -> LevelPartBuilder {
+    LevelPartBuilder::new(LevelBiome::Home)
+        .with_size(120.0, 120.0)
+        .with_count(5)
+        .with_fill_ratio(0.2)
+}
+
+fn combat_part(biome: LevelBiome) -> LevelPartBuilder {
+    LevelPartBuilder::new(biome)
+        .with_size(120.0, 120.0)
+        .with_count(40)
+        .with_fill_ratio(0.2)
+}
+
+fn side_part(biome: LevelBiome) -> LevelPartBuilder {
+    LevelPartBuilder::new(biome)
+        .with_size(60.0, 120.0)
+        .with_count(20)
+        .with_fill_ratio(0.2)
+        .with_region(PartRegion::Voronoi)
+        .with_blend(3.0)
+}
+
+fn corridor_part(biome: LevelBiome) -> LevelPartBuilder {
+    LevelPartBuilder::new(biome)
+        .with_size(120.0, 12.0)
+        .with_count(1)
+        .with_fill_ratio(1.0)
+        .with_points(vec![
+            Vec2::new(-48.0, -4.8),
+            Vec2::new(-48.0, 0.0),
+            Vec2::new(48.0, 0.0),
+            Vec2::new(48.0, -4.8),
+        ])
+}
+
+impl LevelBuilder {
+    // Home first, then combat parts going down with a safe part after every
+    // `combat_per_safe` of them, side branches to the left or right, and a safe part
+    // right before the boss, which comes last on the last floor. Other floors end with
+    // the safe part, where the game puts the portal down. Gated combat parts lock
+    // everything after them, side branches included.
+    pub fn random_run(seed: u64, rules: &RunRules, floor: usize) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed ^ LAYOUT_STREAM);
+        let mut builder = Self::new(seed);
+
+        let mut last = builder.add(Vec2::ZERO, home_part());
+        let mut since_safe = 0;
+        let mut prev_biome = None;
+
+        for _ in 0..rng.random_range(rules.stages.clone()) {
+            if since_safe >= rules.combat_per_safe {
+                last = builder.add_after(last, PartAlign::Down, corridor_part(LevelBiome::Safe));
+                since_safe = 0;
+            }
+
+            let candidates = rules
+                .combat_biomes
+                .iter()
+                .filter(|biome| rules.combat_biomes.len() == 1 || Some(**biome) != prev_biome)
+                .collect::<Vec<_>>();
+            let Some(&&biome) = candidates.choose(&mut rng) else {
+                break;
+            };
+            let mut part = combat_part(biome);
+            if rng.random_bool(rules.gate_chance)
+                && let Some(&condition) = rules.gate_conditions.choose(&mut rng)
+            {
+                part = part.with_gate(condition);
+            }
+            last = builder.add_after(last, PartAlign::Down, part);
+            since_safe += 1;
+            prev_biome = Some(biome);
+
+            if rng.random_bool(rules.side_chance)
+                && let Some(&side) = rules.side_biomes.choose(&mut rng)
+            {
+                let align = if rng.random_bool(0.5) {
+                    PartAlign::Left
+                } else {
+                    PartAlign::Right
+                };
+                builder.add_after(last, align, side_part(side));
+            }
+        }
+
+        last = builder.add_after(last, PartAlign::Down, corridor_part(LevelBiome::Safe));
+        if rules.is_last_floor(floor) {
+            builder.add_after(last, PartAlign::Down, corridor_part(LevelBiome::Boss));
+        }
+
+        builder
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn near(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-3
+    }
+
+    #[test]
+    fn runs_follow_the_rules() {
+        let rules = RunRules::default();
+        for seed in 0..16 {
+            for floor in 0..rules.floors {
+                let builder =
+                    LevelBuilder::random_run(rules.floor_seed(seed, floor), &rules, floor);
+
+                // the main path goes straight down, side branches sit left or right of the
+                // combat part before them
+                let mut main = vec![&builder.parts[0]];
+                for part in &builder.parts[1..] {
+                    let prev = main[main.len() - 1];
+                    let (center, prev_center) = (part.bounds.center(), prev.bounds.center());
+                    let edge = 0.5 * (part.bounds.size() + prev.bounds.size());
+                    if rules.side_biomes.contains(&part.biome) {
+                        assert!(rules.combat_biomes.contains(&prev.biome));
+                        assert!(near(center.y, prev_center.y));
+                        assert!(near((center.x - prev_center.x).abs(), edge.x));
+                    } else {
+                        assert!(near(center.x, prev_center.x));
+                        assert!(near(prev_center.y - center.y, edge.y));
+                        main.push(part);
+                    }
+                }
+                let mut main = main.into_iter().map(|part| part.biome).collect::<Vec<_>>();
+
+                assert_eq!(main[0], LevelBiome::Home);
+                if rules.is_last_floor(floor) {
+                    assert_eq!(main.pop(), Some(LevelBiome::Boss));
+                }
+                assert_eq!(main.pop(), Some(LevelBiome::Safe));
+                assert!(!main.contains(&LevelBiome::Boss));
+
+                // a safe part after every `combat_per_safe` combat parts, the final one may
+                // come sooner
+                let stretches = main[1..]
+                    .split(|biome| *biome == LevelBiome::Safe)
+                    .collect::<Vec<_>>();
+                for (idx, stretch) in stretches.iter().enumerate() {
+                    assert!(
+                        stretch
+                            .iter()
+                            .all(|biome| rules.combat_biomes.contains(biome))
+                    );
+                    if idx + 1 < stretches.len() {
+                        assert_eq!(stretch.len(), rules.combat_per_safe);
+                    } else {
+                        assert!((1..=rules.combat_per_safe).contains(&stretch.len()));
+                    }
+                }
+                let stages = stretches.iter().map(|stretch| stretch.len()).sum::<usize>();
+                assert!(rules.stages.contains(&stages));
+
+                if let Err(err) = builder.build(0.1) {
+                    panic!("seed {seed} floor {floor}: {err}");
+                }
+            }
+        }
+    }
+}
@@ -3,6 +3,7 @@
 use core::f32;
 
 use bevy::{
+    asset::io::file::FileAssetReader,
     audio::{PlaybackMode, Volume},
     prelude::*,
     render::view::RenderLayers,
@@ -11,36 +12,42 @@ use bevy::{
 use bevy_hanabi::HanabiPlugin;
 use bevy_inspector_egui::{bevy_egui::EguiPlugin, quick::WorldInspectorPlugin};
 use bevy_mod_skinned_aabb::SkinnedAabbPlugin;
-use petgraph::visit::EdgeRef;
-use rand::{
-    distr::{Distribution, weighted::WeightedIndex},
-    seq::{IndexedRandom, SliceRandom},
+use rand::{SeedableRng, rngs::StdRng};
+use twg_2025_1::{
+    args::{self, arg},
+    level::{
+        self, Level, LevelBuilder,
+        creatures::CreatureKind,
+        layout::{self, LevelLayout},
+        run::RunRules,
+    },
 };
 
 use crate::{
-    boss::{BossPlugin, BossSpawner},
-    enemy::{
-        Enemy, EnemyPlugin, beetle::Beetle, glutton::Glutton, mushroom::Mushroom, seal::Seal,
-        spider::Spider, stalker::Stalker, tree::Tree, turret::Turret, wolf::Wolf,
-        wormbeak::Wormbeak,
-    },
-    heart::{HeartPlugin, HeartSpawner},
-    level::{Level, LevelBiome, LevelBuilder, LevelPart, LevelPartBuilder, PartAlign},
+    boss::BossPlugin,
+    decoration::DecorationPlugin,
+    enemy::{Enemy, EnemyPlugin},
+    faction::{Faction, FactionPlugin},
+    floor::{FloorEntity, FloorPlugin, Floors},
+    gate::GatePlugin,
+    hazard::HazardPlugin,
+    heart::HeartPlugin,
     model_loader::ModelLoaderPlugin,
     player::{Player, PlayerPlugin},
     projectile::ProjectilePlugin,
     terrain::TerrainPlugin,
     ui::{GameUiPlugin, UserNotify},
-    weapon::{
-        WeaponPlugin, biogun::Biogun, blaster::Blaster, ion_cannon::IonCannon,
-        pulse_rifle::PulseRifle, zapper::Zapper,
-    },
+    weapon::{WeaponPlugin, blaster::Blaster},
 };
 
 mod boss;
+mod decoration;
 mod enemy;
+mod faction;
+mod floor;
+mod gate;
+mod hazard;
 mod heart;
-mod level;
 mod model_loader;
 mod player;
 mod projectile;
@@ -48,8 +55,13 @@ mod terrain;
 mod ui;
 mod weapon;
 
+#[derive(Resource)]
+pub struct GameRng(pub StdRng);
+
 #[derive(Resource)]
 pub enum GameState {
+    // Waiting for the tables the simulation can't run without.
+    Loading,
     Running,
     Paused,
     Win,
@@ -62,6 +74,9 @@ fn gamestate(
     mut user_notify: EventWriter<UserNotify>,
 ) {
     match *state {
+        GameState::Loading => {
+            user_notify.write(UserNotify("Загрузка".to_string(), String::new()));
+        }
         GameState::Win => {
             user_notify.write(UserNotify(
                 "Поздравляем".to_string(),
@@ -106,32 +121,62 @@ fn fullscreen(
 }
 
 fn main() {
-    let mut level_builder = LevelBuilder::new();
-
-    let mut id = level_builder.add(Vec2::ZERO, area_home());
-
-    id = level_builder.add_after(id, PartAlign::Down, area_forest());
-
-    id = level_builder.add_after(id, PartAlign::Down, area_cave());
-    level_builder.add_after(id, PartAlign::Left, area_mushroom());
-    id = level_builder.add_after(id, PartAlign::Down, area_safe());
-
-    id = level_builder.add_after(id, PartAlign::Down, area_temple());
-    level_builder.add_after(id, PartAlign::Right, area_meat());
-    id = level_builder.add_after(id, PartAlign::Down, area_safe());
+    let seed = args::seed();
+
+    // the default plugins set up logging, which the level cache reports through
+    let mut app = App::new();
+    app.add_plugins(DefaultPlugins.set(WindowPlugin {
+        primary_window: Some(Window {
+            title: "Tuonela".to_string(),
+            // mode: WindowMode::BorderlessFullscreen(MonitorSelection::Current),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }));
+
+    let scale = 4.0;
+    let floors = if std::env::args().any(|arg| arg == "--run") {
+        let rules = RunRules::default();
+        (0..rules.floors)
+            .map(|floor| {
+                let seed = rules.floor_seed(seed, floor);
+                let builder = LevelBuilder::random_run(seed, &rules, floor);
+                (
+                    format!("run floor {floor}"),
+                    rules.cache_key(seed, floor, scale),
+                    builder,
+                )
+            })
+            .collect::<Vec<_>>()
+    } else {
+        let layout_path = arg("--layout").unwrap_or(layout::DEFAULT_PATH.to_string());
+        let layout = match LevelLayout::load(&layout_path) {
+            Ok(layout) => layout,
+            Err(err) => panic!("{layout_path}: {err}"),
+        };
+        let builder = LevelBuilder::from_layout(seed, &layout);
+        vec![(layout_path, layout.cache_key(seed, scale), builder)]
+    };
 
-    level_builder.add_after(id, PartAlign::Down, area_boss());
+    let no_cache = std::env::args().any(|arg| arg == "--no-cache");
+    let mut levels = floors
+        .into_iter()
+        .map(|(source, cache_key, builder)| {
+            let build_level = || match builder.build(scale) {
+                Ok(level) => level,
+                Err(err) => panic!("{source}: {err}"),
+            };
+            if no_cache {
+                build_level()
+            } else {
+                let cache = FileAssetReader::get_base_path().join("cache");
+                Level::load_or_build(cache, cache_key, build_level)
+            }
+        })
+        .collect::<Vec<_>>();
+    let level = levels.remove(0);
 
-    App::new()
-        .add_plugins(DefaultPlugins.set(WindowPlugin {
-            primary_window: Some(Window {
-                title: "Tuonela".to_string(),
-                // mode: WindowMode::BorderlessFullscreen(MonitorSelection::Current),
-                ..Default::default()
-            }),
-            ..Default::default()
-        }))
-        .add_plugins(HanabiPlugin)
+    app.add_plugins(HanabiPlugin)
         // .add_plugins(EguiPlugin::default())
         // .add_plugins(WorldInspectorPlugin::default())
         .add_plugins(SkinnedAabbPlugin)
@@ -141,17 +186,27 @@ fn main() {
             ..Default::default()
         })
         .insert_resource(ClearColor(Color::srgba(0.02, 0.02, 0.02, 1.0)))
-        .insert_resource(GameState::Running)
+        .insert_resource(GameState::Loading)
+        .insert_resource(GameRng(StdRng::seed_from_u64(seed)))
         .add_systems(Startup, setup)
         .add_systems(Update, defer_despawn)
         .add_systems(Update, gamestate)
         .add_systems(Update, fullscreen)
         // .add_systems(Update, bury)
-        .add_systems(Update, update_level)
+        .add_systems(
+            Update,
+            (add_creatures, move_creatures, remove_creatures).chain(),
+        )
         .add_systems(Update, grab_cursor)
-        .insert_resource(level_builder.build(4.0))
+        .insert_resource(level)
+        .insert_resource(Floors::new(levels))
         .add_plugins(EnemyPlugin)
         .add_plugins(BossPlugin)
+        .add_plugins(DecorationPlugin)
+        .add_plugins(FactionPlugin)
+        .add_plugins(FloorPlugin)
+        .add_plugins(GatePlugin)
+        .add_plugins(HazardPlugin)
         .add_plugins(HeartPlugin)
         .add_plugins(ModelLoaderPlugin)
         .add_plugins(PlayerPlugin)
@@ -162,218 +217,31 @@ fn main() {
         .run();
 }
 
-const BASE_WIDTH: f32 = 120.0;
-const BASE_HEIGHT: f32 = 120.0;
-
-fn area_home() -> LevelPart {
-    LevelPartBuilder::new(LevelBiome::Home)
-        .with_size(BASE_WIDTH, BASE_HEIGHT)
-        .with_count(5)
-        .with_fill_ratio(0.2)
-        .build()
-}
-
-fn area_safe() -> LevelPart {
-    LevelPartBuilder::new(LevelBiome::Safe)
-        .with_size(BASE_WIDTH, BASE_HEIGHT * 0.1)
-        .with_count(1)
-        .with_fill_ratio(1.0)
-        .with_points(vec![
-            Vec2::new(-BASE_WIDTH * 0.4, -BASE_HEIGHT * 0.1 * 0.4),
-            Vec2::new(-BASE_WIDTH * 0.4, 0.0),
-            Vec2::new(BASE_WIDTH * 0.4, 0.0),
-            Vec2::new(BASE_WIDTH * 0.4, -BASE_HEIGHT * 0.1 * 0.4),
-        ])
-        .build()
-}
-
-fn area_forest() -> LevelPart {
-    LevelPartBuilder::new(LevelBiome::Forest)
-        .with_size(BASE_WIDTH, BASE_HEIGHT)
-        .with_count(40)
-        .with_fill_ratio(0.2)
-        .build()
-}
-
-fn area_cave() -> LevelPart {
-    LevelPartBuilder::new(LevelBiome::Cave)
-        .with_size(BASE_WIDTH, BASE_HEIGHT)
-        .with_count(40)
-        .with_fill_ratio(0.2)
-        .build()
-}
-
-fn area_mushroom() -> LevelPart {
-    LevelPartBuilder::new(LevelBiome::Mushroom)
-        .with_size(BASE_WIDTH * 0.5, BASE_HEIGHT)
-        .with_count(20)
-        .with_fill_ratio(0.2)
-        .build()
-}
-
-fn area_temple() -> LevelPart {
-    LevelPartBuilder::new(LevelBiome::Temple)
-        .with_size(BASE_WIDTH, BASE_HEIGHT)
-        .with_count(40)
-        .with_fill_ratio(0.2)
-        .build()
-}
-
-fn area_meat() -> LevelPart {
-    LevelPartBuilder::new(LevelBiome::Meat)
-        .with_size(BASE_WIDTH * 0.5, BASE_HEIGHT)
-        .with_count(20)
-        .with_fill_ratio(0.2)
-        .build()
-}
-
-fn area_boss() -> LevelPart {
-    LevelPartBuilder::new(LevelBiome::Boss)
-        .with_size(BASE_WIDTH, BASE_HEIGHT * 0.1)
-        .with_count(1)
-        .with_fill_ratio(1.0)
-        .with_points(vec![
-            Vec2::new(-BASE_WIDTH * 0.4, -BASE_HEIGHT * 0.1 * 0.4),
-            Vec2::new(-BASE_WIDTH * 0.4, 0.0),
-            Vec2::new(BASE_WIDTH * 0.4, 0.0),
-            Vec2::new(BASE_WIDTH * 0.4, -BASE_HEIGHT * 0.1 * 0.4),
-        ])
-        .build()
-}
-
 fn setup(
     mut commands: Commands,
     mut window: Single<&mut Window, With<PrimaryWindow>>,
     asset_server: Res<AssetServer>,
     level: Res<Level>,
+    floors: Res<Floors>,
+    mut rng: ResMut<GameRng>,
 ) {
-    let mut enemy_points = vec![];
-    for edge in level.graph.edge_references() {
-        let source = level.graph.node_weight(edge.source()).unwrap();
-        let target = level.graph.node_weight(edge.target()).unwrap();
-        let dir = (target - source).normalize();
-        let dist = source.distance(*target);
-        for _ in 0..10 {
-            enemy_points.push(source + dir * rand::random_range(0.0..=dist));
-        }
-    }
-
-    let mut rng = rand::rng();
-
-    enemy_points.shuffle(&mut rng);
-
-    let mut spawned = 0;
-    while let Some(point) = enemy_points.pop() {
-        if spawned >= 100 {
-            break;
-        }
-
-        let biome = level.biome(point).0;
-        let Ok(dist) = WeightedIndex::new(&biome[3..=7]) else {
-            continue;
-        };
+    info!("Level seed: {}", level.seed());
 
-        let choices = [
-            ["tree", "wolf"],        // forest
-            ["seal", "wormbeak"],    // cave
-            ["mushroom", "stalker"], // mushroom
-            ["spider", "turret"],    // temple
-            ["glutton", "beetle"],   // meat
-        ];
-
-        let Some(choice) = choices[dist.sample(&mut rng)].choose(&mut rng) else {
-            continue;
-        };
-
-        spawned += 1;
-        // println!("{choice}: {point:?}");
-        match *choice {
-            "tree" => commands.spawn(Tree),
-            "wolf" => commands.spawn(Wolf),
-            "seal" => commands.spawn(Seal),
-            "wormbeak" => commands.spawn(Wormbeak),
-            "mushroom" => commands.spawn(Mushroom),
-            "stalker" => commands.spawn(Stalker),
-            "spider" => commands.spawn(Spider),
-            "turret" => commands.spawn(Turret),
-            "glutton" => commands.spawn(Glutton),
-            "beetle" => commands.spawn(Beetle),
-            _ => panic!("Unknown enemy {choice}"),
-        }
-        .insert(Transform::from_xyz(point.x, 0.0, point.y));
-    }
-
-    let player_xy = level.nearest_terrain(1, Vec2::new(0.0, f32::MAX))[0].unwrap();
-    let node = level.nearest_id_terrain(1, player_xy)[0];
-
-    let spawn_point = {
-        let node = level.graph.neighbors(node).next().unwrap();
-        let point = level.graph.node_weight(node).unwrap();
-        player_xy + (point - player_xy).normalize() * 5.0
-    };
+    floor::populate(&mut commands, &level, &mut rng.0, &floors);
 
+    let player_xy = floor::home_point(&level);
+    let spawn_point = floor::step_from(&level, player_xy);
     let step = (spawn_point - player_xy).normalize() * 5.0;
 
-    let home = Vec2::new(0.0, 0.0);
-    let forest = Vec2::new(0.0, -170.0);
-    let cave = Vec2::new(0.0, -340.0);
-    let mushroom = Vec2::new(-1000.0, -170.0);
-    let safe1 = Vec2::new(0.0, -456.0);
-    let temple = Vec2::new(0.0, -572.0);
-    let meat = Vec2::new(1000.0, -286.0);
-    let safe2 = Vec2::new(0.0, -688.0);
-    let boss = Vec2::new(0.0, -750.0);
-
-    for point in [home, safe1, safe2] {
-        let pos = level.nearest_terrain(1, point)[0].unwrap();
-        commands.spawn((
-            HeartSpawner,
-            Transform::from_translation((pos).extend(0.0).xzy()),
-        ));
-    }
-
     commands.spawn((
         Blaster,
+        FloorEntity,
         Transform::from_translation((spawn_point + step * 1.0).extend(0.0).xzy()),
     ));
 
-    commands.spawn((
-        PulseRifle,
-        Transform::from_translation(level.nearest_terrain(1, cave)[0].unwrap().extend(0.0).xzy()),
-    ));
-
-    commands.spawn((
-        Zapper,
-        Transform::from_translation(
-            level.nearest_terrain(1, mushroom)[0]
-                .unwrap()
-                .extend(0.0)
-                .xzy(),
-        ),
-    ));
-
-    commands.spawn((
-        IonCannon,
-        Transform::from_translation(
-            level.nearest_terrain(1, temple)[0]
-                .unwrap()
-                .extend(0.0)
-                .xzy(),
-        ),
-    ));
-
-    commands.spawn((
-        Biogun,
-        Transform::from_translation(level.nearest_terrain(1, meat)[0].unwrap().extend(0.0).xzy()),
-    ));
-
-    commands.spawn((
-        BossSpawner,
-        Transform::from_translation(level.nearest_terrain(1, boss)[0].unwrap().extend(0.0).xzy()),
-    ));
-
     commands.spawn((
         Player::new(100.0),
+        Faction::new(faction::PLAYER),
         Transform::from_xyz(player_xy.x, 0.0, player_xy.y),
     ));
 
@@ -410,15 +278,38 @@ fn setup(
     window.cursor_options.visible = false;
 }
 
-fn update_level(
+fn add_creatures(
     mut level: ResMut<Level>,
-    enemies: Query<(Entity, &GlobalTransform), With<Enemy>>,
-    player: Single<(Entity, &GlobalTransform), With<Player>>,
+    enemies: Query<(Entity, &GlobalTransform), Added<Enemy>>,
+    players: Query<(Entity, &GlobalTransform), Added<Player>>,
 ) {
-    level.clear_creatures();
-    level.add_creature(player.0, player.1.translation());
+    for (player, transform) in players {
+        level.insert_creature(player, CreatureKind::Player, transform.translation());
+    }
     for (enemy, transform) in enemies {
-        level.add_creature(enemy, transform.translation());
+        level.insert_creature(enemy, CreatureKind::Enemy, transform.translation());
+    }
+}
+
+fn move_creatures(
+    mut level: ResMut<Level>,
+    enemies: Query<(Entity, Ref<GlobalTransform>), With<Enemy>>,
+    players: Query<(Entity, Ref<GlobalTransform>), With<Player>>,
+) {
+    for (creature, transform) in enemies.iter().chain(&players) {
+        if transform.is_changed() {
+            level.move_creature(creature, transform.translation());
+        }
+    }
+}
+
+fn remove_creatures(
+    mut level: ResMut<Level>,
+    mut enemies: RemovedComponents<Enemy>,
+    mut players: RemovedComponents<Player>,
+) {
+    for creature in enemies.read().chain(players.read()) {
+        level.remove_creature(creature);
     }
 }
 
@@ -6,7 +6,8 @@ use bevy::{
 
 use crate::{
     boss::Boss,
-    enemy::{AttackKind, Enemy, EnemyMaterial},
+    enemy::{Enemy, EnemyMaterial, EnemyStats},
+    faction::{self, Faction},
     heart::Heart,
     level::Level,
     projectile::SpawnProjectile,
@@ -22,15 +23,9 @@ impl Plugin for ModelLoaderPlugin {
     }
 }
 
-#[derive(Component, Clone, Copy)]
+#[derive(Component, Clone)]
 pub enum ReadyAction {
-    Enemy {
-        attack: AttackKind,
-        attack_range: f32,
-        attack_delay: f32,
-        speed: f32,
-        hp: f32,
-    },
+    Enemy(EnemyStats),
     Weapon {
         offset: Vec3,
         shoot_delay: f32,
@@ -106,7 +101,7 @@ fn load_model(
             .insert(WaitFor::Gltf {
                 name: name.clone(),
                 gltf_handle: assets.load(format!("./models/{name}.glb")),
-                action: *action,
+                action: action.clone(),
                 scale: *scale,
             });
     }
@@ -120,6 +115,32 @@ fn load_model(
                 scale,
             } => {
                 if let Some(gltf) = assets_gltf.get(gltf_handle) {
+                    // the enemy table checks the clips when it loads, this only guards
+                    // against the model changing since
+                    let graph = match action {
+                        ReadyAction::Enemy(stats) => {
+                            let clips = stats
+                                .clips()
+                                .into_iter()
+                                .map(|clip| gltf.named_animations.get(clip).cloned())
+                                .collect::<Option<Vec<_>>>();
+                            let Some(clips) = clips else {
+                                error!("Enemy {name} is missing some of its animations");
+                                commands.entity(entity).despawn();
+                                continue;
+                            };
+                            AnimationGraph::from_clips(clips).0
+                        }
+                        ReadyAction::Weapon { .. } => {
+                            AnimationGraph::from_clips([
+                                gltf.named_animations["idle"].clone(),
+                                gltf.named_animations["shoot"].clone(),
+                            ])
+                            .0
+                        }
+                        ReadyAction::Heart => AnimationGraph::new(),
+                        ReadyAction::Boss => AnimationGraph::new(),
+                    };
                     let scene = commands
                         .spawn((
                             SceneRoot(gltf.scenes[0].clone()),
@@ -132,27 +153,8 @@ fn load_model(
                             WaitFor::Scene {
                                 name: name.clone(),
                                 scene,
-                                graph_handle: graphs.add(match action {
-                                    ReadyAction::Enemy { .. } => {
-                                        AnimationGraph::from_clips([
-                                            gltf.named_animations["idle"].clone(),
-                                            gltf.named_animations["walk"].clone(),
-                                            gltf.named_animations["attack"].clone(),
-                                            gltf.named_animations["death"].clone(),
-                                        ])
-                                        .0
-                                    }
-                                    ReadyAction::Weapon { .. } => {
-                                        AnimationGraph::from_clips([
-                                            gltf.named_animations["idle"].clone(),
-                                            gltf.named_animations["shoot"].clone(),
-                                        ])
-                                        .0
-                                    }
-                                    ReadyAction::Heart => AnimationGraph::new(),
-                                    ReadyAction::Boss => AnimationGraph::new(),
-                                }),
-                                action: *action,
+                                graph_handle: graphs.add(graph),
+                                action: action.clone(),
                                 scale: *scale,
                             },
                             Visibility::default(),
@@ -169,13 +171,7 @@ fn load_model(
             } => {
                 commands.entity(entity).remove::<WaitFor>();
                 match action {
-                    ReadyAction::Enemy {
-                        attack,
-                        attack_range,
-                        attack_delay,
-                        speed,
-                        hp,
-                    } => {
+                    ReadyAction::Enemy(stats) => {
                         let mut anim_player = Entity::PLACEHOLDER;
                         let mut hitbox = Entity::PLACEHOLDER;
                         let mut shoot_point = Entity::PLACEHOLDER;
@@ -228,14 +224,32 @@ fn load_model(
                         };
                         let hitbox = Aabb3d::new(hitbox.translation * scale, hitbox.scale * scale);
 
-                        let shoot_point = match (attack, transforms.get(shoot_point)) {
-                            (AttackKind::Ranged(_), Ok(transform)) => transform.translation,
-                            (AttackKind::Melee(_), _) => Vec3::ZERO,
-                            (AttackKind::Ranged(_), Err(_)) => {
-                                panic!("Ranged enemy {name} doesn't have a a shoot point")
+                        // enemies that only sometimes shoot may spit from the top of the hitbox
+                        let ranged = stats.attacks.iter().any(|attack| !attack.is_melee());
+                        let melee = stats.attacks.iter().any(|attack| attack.is_melee());
+                        let shoot_point = match (ranged, transforms.get(shoot_point)) {
+                            (true, Ok(transform)) => transform.translation,
+                            (false, _) => Vec3::ZERO,
+                            (true, Err(_)) if melee => {
+                                Vec3::from(hitbox.center()).with_y(hitbox.max.y) / *scale
+                            }
+                            (true, Err(_)) => {
+                                error!("Ranged enemy {name} doesn't have a shoot point");
+                                commands.entity(entity).despawn();
+                                continue;
                             }
                         };
 
+                        let clips = stats.clips();
+                        let attack_nodes = stats
+                            .attacks
+                            .iter()
+                            .map(|attack| {
+                                let clip = clips.iter().position(|clip| *clip == attack.animation);
+                                AnimationNodeIndex::new(clip.unwrap() + 1)
+                            })
+                            .collect();
+
                         let radius =
                             0.5 * (hitbox.max.x - hitbox.min.x).min(hitbox.max.z - hitbox.min.z);
 
@@ -249,14 +263,11 @@ fn load_model(
                             .insert(Enemy::new(
                                 *scene,
                                 anim_player,
-                                *attack,
-                                *attack_range,
-                                *attack_delay,
-                                *speed,
-                                *hp,
+                                stats.clone(),
                                 shoot_point,
+                                attack_nodes,
                             ))
-                            .insert(Physics::new(radius, *speed, hitbox, false))
+                            .insert(Physics::new(radius, stats.speed, hitbox, false))
                             .with_child((
                                 // Mesh3d(
                                 //     meshes
@@ -312,12 +323,15 @@ fn load_model(
                         commands.entity(entity).insert(Heart);
                     }
                     ReadyAction::Boss => {
-                        commands.entity(entity).insert(Boss {
-                            attack_delay: 1.0,
-                            timer: 0.0,
-                            max_hp: 4000.0,
-                            hp: 4000.0,
-                        });
+                        commands.entity(entity).insert((
+                            Boss {
+                                attack_delay: 1.0,
+                                timer: 0.0,
+                                max_hp: 4000.0,
+                                hp: 4000.0,
+                            },
+                            Faction::new(faction::BOSS),
+                        ));
                     }
                 }
             }
@@ -154,13 +154,12 @@ fn controller(
 }
 
 fn update_hp(
-    mut commands: Commands,
-    player: Single<(Entity, &mut Player, &ApplyDamage)>,
+    player: Single<(&mut Player, &mut ApplyDamage), Changed<ApplyDamage>>,
     mut game_state: ResMut<GameState>,
 ) {
-    let (entity, mut player, damage) = player.into_inner();
-    commands.entity(entity).remove::<ApplyDamage>();
-    player.hp -= damage.0;
+    let (mut player, mut pending) = player.into_inner();
+    let damage = std::mem::take(pending.bypass_change_detection());
+    player.hp -= damage.damage;
     if player.hp <= 0.0 {
         *game_state = GameState::Lose;
     }
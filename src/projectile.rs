@@ -1,10 +1,10 @@
 use bevy::{math::bounding::Aabb3d, prelude::*, render::view::NoFrustumCulling};
+use serde::Deserialize;
 
 use crate::{
     DeferDespawn, GameState,
-    enemy::Enemy,
+    faction::{Faction, Hostility, HostilityTable},
     level::Level,
-    player::Player,
     projectile::{
         beetle_proj::BeetleProj, biogun_proj::BiogunProj, blaster_proj::BlasterProj,
         boss_proj::BossProj, bullet::Bullet, detonation_bolt::DetonationBolt, explosion::Explosion,
@@ -53,7 +53,7 @@ impl Plugin for ProjectilePlugin {
     }
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Deserialize)]
 pub enum SpawnProjectile {
     Bullet,
     BeetleProj,
@@ -72,7 +72,13 @@ pub enum SpawnProjectile {
 }
 
 impl SpawnProjectile {
-    pub fn spawn(&self, commands: &mut Commands, transform: Transform, damage: Damage) {
+    pub fn spawn(
+        &self,
+        commands: &mut Commands,
+        transform: Transform,
+        faction: Faction,
+        shooter: Entity,
+    ) {
         let mut entity = commands.spawn((transform, NoFrustumCulling));
         match self {
             Self::Bullet => entity.insert(Bullet),
@@ -90,10 +96,31 @@ impl SpawnProjectile {
             Self::WormbeakProj => entity.insert(WormbeakProj),
             Self::ZapperProj => entity.insert(ZapperProj),
         };
+        entity.insert((faction, Shooter(shooter)));
+        if let Self::Explosion = self {
+            entity.insert(Splash);
+        }
+    }
+
+    // Speed along the forward direction it is spawned with and the acceleration it then
+    // flies with, for aiming.
+    pub fn ballistics(&self) -> (f32, Vec3) {
         match self {
-            Self::Explosion => entity.insert(Damage::All),
-            _ => entity.insert(damage),
-        };
+            Self::Bullet => (bullet::SPEED, bullet::ACELERATION),
+            Self::BeetleProj => (beetle_proj::SPEED, beetle_proj::ACELERATION),
+            Self::BiogunProj => (biogun_proj::SPEED, biogun_proj::ACELERATION),
+            Self::BlasterProj => (blaster_proj::SPEED, blaster_proj::ACELERATION),
+            Self::BossProj => (boss_proj::SPEED, boss_proj::ACELERATION),
+            Self::DetonationBolt => (detonation_bolt::SPEED, detonation_bolt::ACELERATION),
+            Self::Explosion => (explosion::SPEED, explosion::ACELERATION),
+            Self::IonCannonProj => (ioncannon_proj::SPEED, ioncannon_proj::ACELERATION),
+            Self::PulseRifleProj => (pulserifle_proj::SPEED, pulserifle_proj::ACELERATION),
+            Self::StalkerProj => (stalker_proj::SPEED, stalker_proj::ACELERATION),
+            Self::TreeProj => (tree_proj::SPEED, tree_proj::ACELERATION),
+            Self::TurretProj => (turret_proj::SPEED, turret_proj::ACELERATION),
+            Self::WormbeakProj => (wormbeak_proj::SPEED, wormbeak_proj::ACELERATION),
+            Self::ZapperProj => (zapper_proj::SPEED, zapper_proj::ACELERATION),
+        }
     }
 }
 
@@ -110,15 +137,34 @@ pub struct Projectile {
     pub on_bounce: Option<SpawnProjectile>,
 }
 
-#[derive(Component, Clone, Copy)]
-pub enum Damage {
-    Player,
-    Enemy,
-    All,
-}
+// Hurts every creature it reaches, whatever the faction.
+#[derive(Component)]
+pub struct Splash;
 
+// Whoever shot the projectile, what it hits turns on them.
 #[derive(Component)]
-pub struct ApplyDamage(pub f32);
+pub struct Shooter(pub Entity);
+
+// Damage to take and who dealt it, hazards hurt on their own. Stays on the creature once
+// taken, so damage queued meanwhile is added to it rather than dropped with it.
+#[derive(Component, Default)]
+pub struct ApplyDamage {
+    pub damage: f32,
+    pub source: Option<Entity>,
+}
+
+// Adds to the damage `target` already has queued, so hits and hazards landing in the
+// same frame all count.
+pub fn apply_damage(commands: &mut Commands, target: Entity, damage: f32, source: Option<Entity>) {
+    commands
+        .entity(target)
+        .entry::<ApplyDamage>()
+        .and_modify(move |mut pending| {
+            pending.damage += damage;
+            pending.source = pending.source.or(source);
+        })
+        .or_insert(ApplyDamage { damage, source });
+}
 
 #[derive(Component)]
 struct Ready; // 1 frame lag in hanabi?
@@ -143,19 +189,32 @@ fn aabb_sphere_intersection(aabb: Aabb3d, center: Vec3, radius: f32) -> bool {
     dmin <= radius.powi(2)
 }
 
+// Creatures further than this from a projectile can't have their hitbox in its way.
+const HIT_REACH: f32 = 10.0;
+
 fn update(
     mut commands: Commands,
-    mut projectiles: Query<(Entity, &mut Projectile, &Damage, &mut Transform), With<Ready>>,
-    transforms: Query<(&GlobalTransform, &Physics, Option<&Player>, Option<&Enemy>)>,
+    mut projectiles: Query<
+        (Entity, &mut Projectile, &Faction, &Shooter, &mut Transform),
+        With<Ready>,
+    >,
+    splashes: Query<(), With<Splash>>,
+    transforms: Query<(&GlobalTransform, &Physics, &Faction)>,
     level: Res<Level>,
+    hostility: Res<HostilityTable>,
+    hostility_tables: Res<Assets<Hostility>>,
     time: Res<Time>,
     game_state: Res<GameState>,
 ) {
+    // the game only runs once the faction table is loaded
     if !matches!(*game_state, GameState::Running) {
         return;
     }
+    let Some(hostility) = hostility.get(&hostility_tables) else {
+        return;
+    };
 
-    for (entity, mut projectile, damage, mut transform) in &mut projectiles {
+    for (entity, mut projectile, faction, shooter, mut transform) in &mut projectiles {
         if projectile.lifetime <= 0.0 || projectile.bounces < 0 {
             commands
                 .entity(entity)
@@ -175,16 +234,15 @@ fn update(
         let desired_pos = pos + offset;
 
         let mut hit = None;
-        for (entity, _) in level.nearest_creatures(5, pos) {
-            let Ok((transform, physics, player, enemy)) = transforms.get(entity) else {
+        let splash = splashes.contains(entity);
+        // every creature in reach, a crowd of friends around the projectile can't hide a foe
+        let reach = offset.length() + HIT_REACH;
+        for (entity, _) in level.creatures_within(pos, reach, None) {
+            let Ok((transform, physics, other)) = transforms.get(entity) else {
                 continue;
             };
-
-            match (player, enemy, damage) {
-                (Some(_), None, Damage::Player) => {}
-                (None, Some(_), Damage::Enemy) => {}
-                (_, _, Damage::All) => {}
-                _ => continue,
+            if !splash && !hostility.hostile(faction, other) {
+                continue;
             }
 
             let inverse = transform.compute_matrix().inverse();
@@ -205,17 +263,20 @@ fn update(
                 .entity(entity)
                 .remove::<Projectile>()
                 .insert(DeferDespawn(projectile.particle_lifetime));
-            commands.entity(hit).insert(ApplyDamage(projectile.damage));
+            apply_damage(&mut commands, hit, projectile.damage, Some(shooter.0));
             continue;
         }
 
-        let new_pos = level.binary_search(pos, desired_pos, 8);
-        if new_pos.distance(desired_pos) >= f32::EPSILON {
-            transform.look_to(dir.reflect(level.normal_3d(new_pos.xz())), Vec3::Y);
+        let hit = Dir3::new(offset)
+            .ok()
+            .and_then(|offset_dir| level.raycast(pos, offset_dir, offset.length()));
+        let new_pos = hit.map_or(desired_pos, |hit| hit.point);
+        if let Some(hit) = hit {
+            transform.look_to(dir.reflect(hit.normal), Vec3::Y);
             projectile.bounces -= 1;
             projectile.velocity = -projectile.velocity * 0.5;
             if let Some(action) = projectile.on_bounce {
-                action.spawn(&mut commands, transform.clone(), *damage);
+                action.spawn(&mut commands, transform.clone(), faction.clone(), shooter.0);
             }
         }
         transform.translation = new_pos;
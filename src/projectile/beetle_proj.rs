@@ -7,6 +7,9 @@ use bevy_hanabi::{
 
 use crate::projectile::Projectile;
 
+pub const SPEED: f32 = 30.0;
+pub const ACELERATION: Vec3 = Vec3::ZERO;
+
 #[derive(Component)]
 pub struct BeetleProj;
 
@@ -24,7 +27,6 @@ pub fn setup(
     let size = 0.1;
 
     let damage = 26.0;
-    let speed = 30.0;
 
     let effect = effect.get_or_insert({
         let writer = ExprWriter::new();
@@ -69,9 +71,9 @@ pub fn setup(
     for entity in entities {
         commands.entity(entity).insert((
             Projectile {
-                speed,
+                speed: SPEED,
                 velocity: Vec3::ZERO,
-                aceleration: Vec3::ZERO,
+                aceleration: ACELERATION,
                 lifetime: 3.0,
                 particle_lifetime,
                 bounces: 0,
@@ -7,6 +7,9 @@ use bevy_hanabi::{
 
 use crate::projectile::Projectile;
 
+pub const SPEED: f32 = 50.0;
+pub const ACELERATION: Vec3 = Vec3::ZERO;
+
 #[derive(Component)]
 pub struct Bullet;
 
@@ -60,9 +63,9 @@ pub fn setup(
     for entity in entities {
         commands.entity(entity).insert((
             Projectile {
-                speed: 50.0,
+                speed: SPEED,
                 velocity: Vec3::ZERO,
-                aceleration: Vec3::ZERO,
+                aceleration: ACELERATION,
                 lifetime: 3.0,
                 particle_lifetime,
                 bounces: 3,
@@ -5,7 +5,12 @@ use bevy_hanabi::{
     SizeOverLifetimeModifier, SpawnerSettings,
 };
 
-use crate::projectile::Projectile;
+use crate::{
+    enemy::perception::Noise, level::Level, projectile::Projectile, terrain::TerrainCarved,
+};
+
+pub const SPEED: f32 = 0.0;
+pub const ACELERATION: Vec3 = Vec3::ZERO;
 
 #[derive(Component)]
 pub struct Explosion;
@@ -14,11 +19,16 @@ pub fn setup(
     mut commands: Commands,
     mut effects: ResMut<Assets<EffectAsset>>,
     mut effect: Local<Option<Handle<EffectAsset>>>,
-    entities: Query<Entity, Added<Explosion>>,
+    entities: Query<(Entity, &Transform), Added<Explosion>>,
     asset_server: Res<AssetServer>,
+    mut level: ResMut<Level>,
+    mut carved: EventWriter<TerrainCarved>,
 ) {
     let radius = 5.0;
+    let carve_radius = 2.5;
+    let carve_depth = 4.0;
     let particle_lifetime = 0.5;
+    let noise_radius = 40.0;
     let effect = effect.get_or_insert({
         let writer = ExprWriter::new();
         let init_age = SetAttributeModifier::new(Attribute::AGE, writer.lit(0.0).expr());
@@ -54,12 +64,19 @@ pub fn setup(
             }),
         )
     });
-    for entity in entities {
+    for (entity, transform) in entities {
+        if let Some(area) = level.carve(transform.translation, carve_radius, carve_depth) {
+            carved.write(TerrainCarved(area));
+        }
+        commands.send_event(Noise {
+            pos: transform.translation.xz(),
+            radius: noise_radius,
+        });
         commands.entity(entity).insert((
             Projectile {
-                speed: 0.0,
+                speed: SPEED,
                 velocity: Vec3::ZERO,
-                aceleration: Vec3::ZERO,
+                aceleration: ACELERATION,
                 lifetime: 0.5,
                 particle_lifetime,
                 bounces: 0,
@@ -7,6 +7,9 @@ use bevy_hanabi::{
 
 use crate::projectile::{Projectile, SpawnProjectile};
 
+pub const SPEED: f32 = 20.0;
+pub const ACELERATION: Vec3 = Vec3::new(0.0, -10.0, 0.0);
+
 #[derive(Component)]
 pub struct IonCannonProj;
 
@@ -24,7 +27,6 @@ pub fn setup(
     let size = 0.05;
 
     let damage = 25.0;
-    let speed = 20.0;
 
     let effect = effect.get_or_insert({
         let writer = ExprWriter::new();
@@ -74,9 +76,9 @@ pub fn setup(
     for entity in entities {
         commands.entity(entity).insert((
             Projectile {
-                speed,
+                speed: SPEED,
                 velocity: Vec3::ZERO,
-                aceleration: Vec3::new(0.0, -10.0, 0.0),
+                aceleration: ACELERATION,
                 lifetime: 3.0,
                 particle_lifetime,
                 bounces: 2,
@@ -1,3 +1,5 @@
+use std::collections::HashMap;
+
 use bevy::{
     asset::RenderAssetUsages,
     image::{ImageAddressMode, ImageFilterMode, ImageSampler, ImageSamplerDescriptor},
@@ -12,6 +14,7 @@ use imageproc::image::{GenericImageView, imageops::FilterType};
 
 use crate::{
     GameState,
+    floor::{FloorChanged, Floors},
     level::{BiomePixel, Level},
     player::Player,
 };
@@ -23,12 +26,19 @@ impl Plugin for TerrainPlugin {
         app.add_plugins(MaterialPlugin::<
             ExtendedMaterial<StandardMaterial, TerrainMaterial>,
         >::default());
-        app.add_systems(Update, init.run_if(resource_added::<Level>));
-        app.add_systems(Update, init_chunks.after(init));
+        app.add_systems(Startup, init);
+        app.add_systems(Update, init.run_if(on_event::<FloorChanged>));
+        app.add_event::<TerrainCarved>();
+        app.add_systems(
+            Update,
+            (stream_chunks, rebuild_chunks, mesh_chunks)
+                .chain()
+                .after(init)
+                .run_if(resource_exists::<ChunkGrid>),
+        );
         app.add_systems(Update, update_lightmap.after(init));
         app.add_systems(Update, physics.after(init));
 
-        let texture_size = app.world().resource::<Level>().texture_size().as_uvec2();
         let mut images = app.world_mut().resource_mut::<Assets<Image>>();
         let textures = Textures::new(
             &mut *images,
@@ -37,20 +47,154 @@ impl Plugin for TerrainPlugin {
                 "mud", "rock",
             ],
         );
-        let lightmap = DynamicLightmap::new(&mut *images, texture_size / 8);
 
         app.insert_resource(textures);
-        app.insert_resource(lightmap);
     }
 }
 
 #[derive(Component)]
-struct Chunk;
+struct Chunk {
+    coord: UVec2,
+    lod: u32,
+    out_of_range: f32,
+}
+
+#[derive(Component)]
+struct Remesh;
 
 impl Chunk {
     const MESH_SIZE: u32 = 128;
+    // Max distance from the player for each LOD, the last one is the streaming radius.
+    const LOD_DISTANCES: [f32; 4] = [48.0, 96.0, 160.0, 256.0];
+    const UNLOAD_DELAY: f32 = 5.0;
+    const MESH_BUDGET: usize = 4;
+
+    fn lod(dist: f32) -> Option<u32> {
+        Self::LOD_DISTANCES
+            .iter()
+            .position(|max_dist| dist <= *max_dist)
+            .map(|lod| lod as u32)
+    }
+
+    // `edge_lods` are the neighbour LODs at the -x, +x, -y and +y edges of the mesh grid.
+    // Edges next to a coarser neighbour are interpolated along its vertices to avoid cracks.
+    fn mesh(level: &Level, chunk_pos: Vec2, lod: u32, edge_lods: [u32; 4]) -> Mesh {
+        let chunk_scale = Self::MESH_SIZE as f32 * level.pixel_size();
+
+        let height = |pos: Vec2| {
+            level
+                .height(chunk_pos + Vec2::new(pos.x, -pos.y) * chunk_scale)
+                .max(0.0)
+        };
+
+        let stitched_height = |pos: Vec2| {
+            let edges = [
+                (pos.x == -0.5, Vec2::Y),
+                (pos.x == 0.5, Vec2::Y),
+                (pos.y == -0.5, Vec2::X),
+                (pos.y == 0.5, Vec2::X),
+            ];
+            for ((on_edge, along), edge_lod) in edges.into_iter().zip(edge_lods) {
+                if !on_edge || edge_lod <= lod {
+                    continue;
+                }
+                let step = 1.0 / (Self::MESH_SIZE >> edge_lod) as f32;
+                let t = (pos.dot(along) + 0.5) / step;
+                let base = pos - along * pos.dot(along);
+                let from = base + along * (t.floor() * step - 0.5);
+                let to = base + along * ((t.floor() + 1.0) * step - 0.5).min(0.5);
+                return height(from).lerp(height(to), t.fract());
+            }
+            height(pos)
+        };
+
+        let mut builder =
+            MeshBuilder::grid(UVec2::splat((Self::MESH_SIZE >> lod) + 1), &stitched_height);
+
+        for [_, y, z] in &mut builder.positions {
+            (*y, *z) = (*z, -*y);
+        }
+
+        let mut mesh = builder.build();
+        mesh.generate_tangents().unwrap();
+        mesh
+    }
+
+    fn biome_mask(level: &Level, chunk_pos: Vec2) -> Image {
+        let chunk_scale = Self::MESH_SIZE as f32 * level.pixel_size();
+        let chunk_texture_size = (Self::MESH_SIZE * Self::MESH_SIZE) as usize;
+
+        let biomes_total = BiomePixel::END_BIOME - BiomePixel::START_BIOME;
+        let mut biome_mask = vec![0; chunk_texture_size * biomes_total];
+        for y in 0..Self::MESH_SIZE {
+            for x in 0..Self::MESH_SIZE {
+                let pos = UVec2::new(x, y).as_vec2() / (Self::MESH_SIZE - 1) as f32 - 0.5;
+                let biome = level.biome(chunk_pos + pos * chunk_scale).0;
+                let base_offset = (x + y * Self::MESH_SIZE) as usize;
+                for biome_idx in 0..biomes_total {
+                    biome_mask[base_offset + chunk_texture_size * biome_idx] =
+                        (255.0 * biome[BiomePixel::START_BIOME + biome_idx].clamp(0.0, 1.0)) as u8;
+                }
+            }
+        }
+
+        Image::new(
+            Extent3d {
+                width: Self::MESH_SIZE,
+                height: Self::MESH_SIZE,
+                depth_or_array_layers: biomes_total as u32,
+            },
+            TextureDimension::D2,
+            biome_mask,
+            TextureFormat::R8Unorm,
+            RenderAssetUsages::RENDER_WORLD,
+        )
+    }
 }
 
+#[derive(Resource)]
+struct ChunkGrid {
+    root: Entity,
+    count: UVec2,
+    origin: Vec2,
+    size: Vec2,
+    loaded: HashMap<UVec2, (Entity, u32)>,
+}
+
+impl ChunkGrid {
+    fn center(&self, coord: UVec2) -> Vec2 {
+        (coord.as_vec2() + 0.5) * self.size + self.origin
+    }
+
+    // Cells overlapping the square of half size `radius` around `pos`.
+    fn cells_around(&self, pos: Vec2, radius: f32) -> IRect {
+        let cell = |pos: Vec2| ((pos - self.origin) / self.size).floor().as_ivec2();
+        IRect {
+            min: cell(pos - radius).max(IVec2::ZERO),
+            max: cell(pos + radius).min(self.count.as_ivec2() - 1),
+        }
+    }
+
+    fn lod(&self, coord: IVec2) -> Option<u32> {
+        let coord = UVec2::try_from(coord).ok()?;
+        self.loaded.get(&coord).map(|(_, lod)| *lod)
+    }
+
+    fn neighbours(&self, coord: UVec2) -> [IVec2; 4] {
+        let coord = coord.as_ivec2();
+        // mesh grid y is flipped relative to the world z
+        [
+            coord - IVec2::X,
+            coord + IVec2::X,
+            coord + IVec2::Y,
+            coord - IVec2::Y,
+        ]
+    }
+}
+
+#[derive(Event)]
+pub struct TerrainCarved(pub Rect);
+
 #[derive(Resource)]
 struct Textures {
     albedo: Handle<Image>,
@@ -179,6 +323,8 @@ pub struct Physics {
     pub move_vec: Vec2,
     pub look_to: Dir2,
     pub ignore_overlap: bool,
+    // Set by the ground the creature stands on, 1.0 outside hazards.
+    pub speed_scale: f32,
 }
 
 impl Physics {
@@ -190,103 +336,191 @@ impl Physics {
             move_vec: Vec2::ZERO,
             look_to: Dir2::NEG_Y,
             ignore_overlap,
+            speed_scale: 1.0,
         }
     }
+
+    // Ground velocity the creature moves with this frame, as in `physics`.
+    pub fn velocity(&self) -> Vec2 {
+        self.move_vec.normalize_or_zero()
+            * self.move_vec.length().min(1.0)
+            * self.speed
+            * self.speed_scale
+    }
 }
 
-fn init(mut commands: Commands, level: Res<Level>) {
+// Runs again for every floor, the chunks depend on the level size. Each floor keeps
+// its own lightmap, so what was explored there is still known on a return trip.
+fn init(
+    mut commands: Commands,
+    mut images: ResMut<Assets<Image>>,
+    level: Res<Level>,
+    mut floors: ResMut<Floors>,
+    grid: Option<Res<ChunkGrid>>,
+) {
+    if let Some(grid) = grid {
+        commands.entity(grid.root).despawn();
+    }
+
     let texture_size = level.texture_size().as_uvec2();
+    let lightmap = match floors.lightmap() {
+        Some(lightmap) => DynamicLightmap(lightmap.clone()),
+        None => {
+            let lightmap = DynamicLightmap::new(&mut images, texture_size / 8);
+            floors.set_lightmap(lightmap.0.clone());
+            lightmap
+        }
+    };
+    commands.insert_resource(lightmap);
+
     let chunk_size = UVec2::splat(Chunk::MESH_SIZE);
     let chunks_count = (texture_size / chunk_size) + (texture_size % chunk_size).min(UVec2::ONE);
     let starting_point = level.bounds().min;
     let scale = chunk_size.as_vec2() * level.bounds().size() / texture_size.as_vec2();
 
-    let mut terrain = commands.spawn((
-        Name::new("Terrain"),
-        Transform::default(),
-        Visibility::default(),
-    ));
-
-    for y in 0..chunks_count.y {
-        for x in 0..chunks_count.x {
-            let pos = Vec2::new(x as f32 + 0.5, y as f32 + 0.5) * scale + starting_point;
-            terrain.with_child((
-                Chunk,
-                Name::new(format!("Chunk ({x} {y})")),
-                Transform::from_translation(pos.extend(0.0).xzy())
-                    .with_scale(scale.extend(1.0).xzy()),
-                Visibility::default(),
-            ));
+    let root = commands
+        .spawn((
+            Name::new("Terrain"),
+            Transform::default(),
+            Visibility::default(),
+        ))
+        .id();
+
+    commands.insert_resource(ChunkGrid {
+        root,
+        count: chunks_count,
+        origin: starting_point,
+        size: scale,
+        loaded: HashMap::new(),
+    });
+}
+
+fn stream_chunks(
+    mut commands: Commands,
+    mut grid: ResMut<ChunkGrid>,
+    mut chunks: Query<&mut Chunk>,
+    player: Single<&Transform, With<Player>>,
+    time: Res<Time>,
+) {
+    let player_pos = player.translation.xz();
+    let mut jobs = vec![];
+
+    // only cells in streaming range can get a LOD, loaded ones outside of it just unload
+    let cells = grid.cells_around(
+        player_pos,
+        Chunk::LOD_DISTANCES[Chunk::LOD_DISTANCES.len() - 1],
+    );
+    let coords = (cells.min.y..=cells.max.y)
+        .flat_map(|y| (cells.min.x..=cells.max.x).map(move |x| IVec2::new(x, y).as_uvec2()))
+        .chain(
+            grid.loaded
+                .keys()
+                .filter(|coord| !cells.contains(coord.as_ivec2()))
+                .copied(),
+        )
+        .collect::<Vec<_>>();
+
+    for coord in coords {
+        let rect = Rect::from_center_size(grid.center(coord), grid.size);
+        let dist = player_pos.distance(player_pos.clamp(rect.min, rect.max));
+        let lod = Chunk::lod(dist);
+
+        match (grid.loaded.get(&coord).copied(), lod) {
+            (Some((entity, cur_lod)), Some(lod)) => {
+                chunks.get_mut(entity).unwrap().out_of_range = 0.0;
+                if cur_lod != lod {
+                    jobs.push((dist, coord, lod));
+                }
+            }
+            (Some((entity, _)), None) => {
+                let mut chunk = chunks.get_mut(entity).unwrap();
+                chunk.out_of_range += time.delta_secs();
+                if chunk.out_of_range > Chunk::UNLOAD_DELAY {
+                    commands.entity(entity).despawn();
+                    grid.loaded.remove(&coord);
+                }
+            }
+            (None, Some(lod)) => jobs.push((dist, coord, lod)),
+            (None, None) => {}
+        }
+    }
+
+    jobs.sort_by(|(a, ..), (b, ..)| a.total_cmp(b));
+
+    for (_, coord, lod) in jobs.into_iter().take(Chunk::MESH_BUDGET) {
+        match grid.loaded.get(&coord).copied() {
+            Some((entity, _)) => {
+                chunks.get_mut(entity).unwrap().lod = lod;
+                commands.entity(entity).insert(Remesh);
+                grid.loaded.insert(coord, (entity, lod));
+            }
+            None => {
+                let pos = grid.center(coord);
+                let entity = commands
+                    .spawn((
+                        Chunk {
+                            coord,
+                            lod,
+                            out_of_range: 0.0,
+                        },
+                        Remesh,
+                        Name::new(format!("Chunk ({} {})", coord.x, coord.y)),
+                        Transform::from_translation(pos.extend(0.0).xzy())
+                            .with_scale(grid.size.extend(1.0).xzy()),
+                        Visibility::default(),
+                    ))
+                    .id();
+                commands.entity(grid.root).add_child(entity);
+                grid.loaded.insert(coord, (entity, lod));
+            }
+        }
+
+        for neighbour in grid.neighbours(coord) {
+            let Ok(neighbour) = UVec2::try_from(neighbour) else {
+                continue;
+            };
+            if let Some((entity, _)) = grid.loaded.get(&neighbour) {
+                commands.entity(*entity).try_insert(Remesh);
+            }
         }
     }
 }
 
-fn init_chunks(
+fn mesh_chunks(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut images: ResMut<Assets<Image>>,
     mut materials: ResMut<Assets<ExtendedMaterial<StandardMaterial, TerrainMaterial>>>,
     level: Res<Level>,
+    grid: Res<ChunkGrid>,
     textures: Res<Textures>,
     lightmap: Res<DynamicLightmap>,
-    chunks: Query<(Entity, &Transform), Added<Chunk>>,
+    chunks: Query<(Entity, &Chunk, &Transform, Has<Mesh3d>), With<Remesh>>,
 ) {
-    let mut meshes_queue = Parallel::<Vec<(Entity, Mesh, Image)>>::default();
+    let mut meshes_queue = Parallel::<Vec<(Entity, Mesh, Option<Image>)>>::default();
 
     chunks.par_iter().for_each_init(
         || meshes_queue.borrow_local_mut(),
-        |meshes, (entity, transform)| {
+        |meshes, (entity, chunk, transform, has_mesh)| {
             let chunk_pos = transform.translation.xz();
-            let chunk_scale = Chunk::MESH_SIZE as f32 * level.pixel_size();
-
-            let mut builder = MeshBuilder::grid(UVec2::splat(Chunk::MESH_SIZE), &|mut pos| {
-                pos.y = -pos.y;
-                level.height(chunk_pos + pos * chunk_scale).max(0.0)
-            });
-
-            for [_, y, z] in &mut builder.positions {
-                (*y, *z) = (*z, -*y);
-            }
-
-            let chunk_texture_size = (Chunk::MESH_SIZE * Chunk::MESH_SIZE) as usize;
-
-            let biomes_total = BiomePixel::END_BIOME - BiomePixel::START_BIOME;
-            let mut biome_mask = vec![0; chunk_texture_size * biomes_total];
-            for y in 0..Chunk::MESH_SIZE {
-                for x in 0..Chunk::MESH_SIZE {
-                    let pos = UVec2::new(x, y).as_vec2() / (Chunk::MESH_SIZE - 1) as f32 - 0.5;
-                    let biome = level.biome(chunk_pos + pos * chunk_scale).0;
-                    let base_offset = (x + y * Chunk::MESH_SIZE) as usize;
-                    for biome_idx in 0..biomes_total {
-                        biome_mask[base_offset + chunk_texture_size * biome_idx] = (255.0
-                            * biome[BiomePixel::START_BIOME + biome_idx].clamp(0.0, 1.0))
-                            as u8;
-                    }
-                }
-            }
-            let biome_mask = Image::new(
-                Extent3d {
-                    width: Chunk::MESH_SIZE,
-                    height: Chunk::MESH_SIZE,
-                    depth_or_array_layers: biomes_total as u32,
-                },
-                TextureDimension::D2,
-                biome_mask,
-                TextureFormat::R8Unorm,
-                RenderAssetUsages::RENDER_WORLD,
-            );
-
-            let mut mesh = builder.build();
-            mesh.generate_tangents().unwrap();
-
+            let edge_lods = grid
+                .neighbours(chunk.coord)
+                .map(|neighbour| grid.lod(neighbour).unwrap_or(chunk.lod));
+            let mesh = Chunk::mesh(&level, chunk_pos, chunk.lod, edge_lods);
+            let biome_mask = (!has_mesh).then(|| Chunk::biome_mask(&level, chunk_pos));
             meshes.push((entity, mesh, biome_mask));
         },
     );
 
     for (entity, mesh, biome_mask) in meshes_queue.drain() {
-        commands.entity(entity).insert((
+        let mut entity = commands.entity(entity);
+        entity.remove::<Remesh>().insert(Mesh3d(meshes.add(mesh)));
+
+        let Some(biome_mask) = biome_mask else {
+            continue;
+        };
+        entity.insert((
             NotShadowCaster,
-            Mesh3d(meshes.add(mesh)),
             MeshMaterial3d(materials.add(ExtendedMaterial {
                 base: StandardMaterial::default(),
                 extension: TerrainMaterial {
@@ -306,6 +540,29 @@ fn init_chunks(
     }
 }
 
+fn rebuild_chunks(
+    mut commands: Commands,
+    mut carved: EventReader<TerrainCarved>,
+    grid: Res<ChunkGrid>,
+    chunks: Query<(Entity, &Transform), With<Chunk>>,
+) {
+    let areas = carved.read().map(|carved| carved.0).collect::<Vec<_>>();
+    if areas.is_empty() {
+        return;
+    }
+
+    for (entity, transform) in chunks {
+        let chunk_rect = Rect::from_center_size(transform.translation.xz(), grid.size);
+        if areas
+            .iter()
+            .all(|area| area.intersect(chunk_rect).is_empty())
+        {
+            continue;
+        }
+        commands.entity(entity).insert(Remesh);
+    }
+}
+
 fn update_lightmap(
     level: Res<Level>,
     lightmap: ResMut<DynamicLightmap>,
@@ -361,7 +618,7 @@ fn physics(
     }
 
     for (entity, physics) in queries {
-        let speed = physics.move_vec.length().min(1.0) * physics.speed;
+        let speed = physics.move_vec.length().min(1.0) * physics.speed * physics.speed_scale;
         let move_vec = physics.move_vec.normalize_or_zero();
 
         let pos_3d = transforms.get(entity).unwrap().translation;
@@ -369,9 +626,9 @@ fn physics(
 
         if !physics.ignore_overlap {
             if let Some((entity, _)) = level
-                .nearest_creatures(2, desired_pos.extend(pos_3d.y).xzy())
+                .nearest_creatures(2, desired_pos.extend(pos_3d.y).xzy(), None)
                 .into_iter()
-                .nth(1)
+                .find(|(other, _)| *other != entity)
             {
                 if let Ok(other_pos) = transforms.get(entity) {
                     let other_pos = other_pos.translation.xz();
@@ -1,10 +1,32 @@
-use bevy::{color::palettes::css, prelude::*};
+use std::time::Duration;
 
-use crate::player::Player;
+use bevy::{
+    asset::RenderAssetUsages,
+    color::palettes::css,
+    prelude::*,
+    render::render_resource::{Extent3d, TextureDimension, TextureFormat},
+    time::common_conditions::on_timer,
+};
+use imageproc::{
+    drawing::{draw_filled_circle_mut, draw_hollow_rect_mut, draw_line_segment_mut},
+    image::{Rgb, RgbImage},
+    rect,
+};
+use petgraph::visit::EdgeRef;
+
+use crate::{
+    floor::FloorEntity,
+    heart::Heart,
+    level::{Level, LevelBiome},
+    player::Player,
+    terrain::DynamicLightmap,
+    weapon::Weapon,
+};
 
 const CROSSHAIR: f32 = 20.0;
 const HPBAR: f32 = 50.0;
 const INVENTORY: f32 = 100.0;
+const MINIMAP: f32 = 220.0;
 
 pub struct GameUiPlugin;
 
@@ -16,6 +38,21 @@ impl Plugin for GameUiPlugin {
 
         app.add_event::<UserNotify>();
         app.add_systems(Update, update_notification);
+
+        app.add_systems(
+            Update,
+            build_map.run_if(resource_exists_and_changed::<DynamicLightmap>),
+        );
+        app.add_systems(
+            Update,
+            (
+                toggle_map,
+                follow_player,
+                update_map.run_if(on_timer(Duration::from_millis(100))),
+            )
+                .after(build_map)
+                .run_if(resource_exists::<Map>),
+        );
     }
 }
 
@@ -88,6 +125,240 @@ fn update_notification(
     color2.set_alpha(alpha);
 }
 
+// Same size as the lightmap, so one map pixel is one lightmap pixel.
+#[derive(Resource)]
+struct Map {
+    // biome colours and roads, before the fog
+    base: RgbImage,
+    image: Handle<Image>,
+    bounds: Rect,
+    boss_arena: Option<Rect>,
+}
+
+impl Map {
+    // Lightmap value above which an area counts as discovered.
+    const DISCOVERED: f32 = 0.05;
+    // How many map pixels around the player the minimap shows.
+    const MINIMAP_SPAN: f32 = 80.0;
+
+    fn size(&self) -> Vec2 {
+        Vec2::new(self.base.width() as f32, self.base.height() as f32)
+    }
+
+    fn to_map(&self, world_pos: Vec2) -> Vec2 {
+        (world_pos - self.bounds.min) / self.bounds.size() * self.size()
+    }
+}
+
+#[derive(Component)]
+struct MinimapView;
+
+#[derive(Component)]
+struct FullMapView;
+
+// Backdrop around `FullMapView`, shown and hidden together with it.
+#[derive(Component)]
+struct FullMap;
+
+// Rebuilt with the lightmap, which is swapped for every floor.
+fn build_map(
+    mut commands: Commands,
+    mut images: ResMut<Assets<Image>>,
+    mut minimap: Single<&mut ImageNode, (With<MinimapView>, Without<FullMapView>)>,
+    mut full_map: Single<(&mut ImageNode, &mut Node), With<FullMapView>>,
+    level: Res<Level>,
+    lightmap: Res<DynamicLightmap>,
+    map: Option<Res<Map>>,
+) {
+    let Some(size) = images.get(&lightmap.0).map(|image| image.size()) else {
+        return;
+    };
+    if let Some(map) = map {
+        images.remove(&map.image);
+    }
+
+    let to_world = |x: f32, y: f32| {
+        level.bounds().min + Vec2::new(x, y) / size.as_vec2() * level.bounds().size()
+    };
+    let to_map = |pos: Vec2| (pos - level.bounds().min) / level.bounds().size() * size.as_vec2();
+
+    let mut base = RgbImage::from_fn(size.x, size.y, |x, y| {
+        let pos = to_world(x as f32 + 0.5, y as f32 + 0.5);
+        let color = level.biome(pos).color();
+        let shade = if level.height(pos) < 0.0 { 1.0 } else { 0.25 };
+        Rgb(color.map(|c| (c * shade) as u8))
+    });
+
+    for edge in level.graph.edge_references() {
+        let source = to_map(level.graph[edge.source()]);
+        let target = to_map(level.graph[edge.target()]);
+        draw_line_segment_mut(
+            &mut base,
+            (source.x, source.y),
+            (target.x, target.y),
+            Rgb([240, 230, 210]),
+        );
+    }
+
+    let image = images.add(Image::new_fill(
+        Extent3d {
+            width: size.x,
+            height: size.y,
+            ..Default::default()
+        },
+        TextureDimension::D2,
+        &[0, 0, 0, 255],
+        TextureFormat::Rgba8UnormSrgb,
+        RenderAssetUsages::all(),
+    ));
+
+    minimap.image = image.clone();
+    let (full_map_image, full_map_node) = &mut *full_map;
+    full_map_image.image = image.clone();
+    full_map_node.aspect_ratio = Some(size.x as f32 / size.y as f32);
+
+    commands.insert_resource(Map {
+        base,
+        image,
+        bounds: level.bounds(),
+        boss_arena: level
+            .parts()
+            .iter()
+            .find(|part| part.biome() == LevelBiome::Boss)
+            .map(|part| part.bounds()),
+    });
+}
+
+fn toggle_map(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut minimap: Single<&mut Node, (With<MinimapView>, Without<FullMapView>)>,
+    mut full_map: Single<&mut Visibility, With<FullMap>>,
+) {
+    if !keys.just_pressed(KeyCode::KeyM) {
+        return;
+    }
+    let show_full = !matches!(**full_map, Visibility::Visible);
+    **full_map = if show_full {
+        Visibility::Visible
+    } else {
+        Visibility::Hidden
+    };
+    minimap.display = if show_full {
+        Display::None
+    } else {
+        Display::Flex
+    };
+}
+
+fn follow_player(
+    map: Res<Map>,
+    mut minimap: Single<&mut ImageNode, With<MinimapView>>,
+    player: Single<&GlobalTransform, With<Player>>,
+) {
+    let size = map.size();
+    let half = Vec2::splat(Map::MINIMAP_SPAN * 0.5).min(size * 0.5);
+    let center = map
+        .to_map(player.translation().xz())
+        .clamp(half, size - half);
+    minimap.rect = Some(Rect::from_center_half_size(center, half));
+}
+
+fn update_map(
+    map: Res<Map>,
+    mut images: ResMut<Assets<Image>>,
+    lightmap: Res<DynamicLightmap>,
+    player: Single<(&Player, &GlobalTransform)>,
+    transforms: Query<&GlobalTransform>,
+    hearts: Query<&GlobalTransform, With<Heart>>,
+    weapons: Query<&GlobalTransform, (With<Weapon>, With<FloorEntity>)>,
+) {
+    let Some(fog) = images
+        .get(&lightmap.0)
+        .and_then(|image| image.data.as_ref())
+        .map(|data| {
+            data.chunks_exact(4)
+                .map(|bytes| f32::from_le_bytes(bytes.try_into().unwrap()))
+                .collect::<Vec<_>>()
+        })
+    else {
+        return;
+    };
+
+    let (width, height) = map.base.dimensions();
+    let to_map = |pos: Vec2| map.to_map(pos);
+    let discovered = |pos: Vec2| {
+        let pos = to_map(pos).as_uvec2().min(UVec2::new(width, height) - 1);
+        fog.get((pos.y * width + pos.x) as usize)
+            .is_some_and(|fog| *fog > Map::DISCOVERED)
+    };
+
+    let mut image = map.base.clone();
+    for (pixel, fog) in image.pixels_mut().zip(&fog) {
+        let fog = (fog * 4.0).clamp(0.0, 1.0);
+        pixel.0 = pixel.0.map(|c| (c as f32 * fog) as u8);
+    }
+
+    if let Some(bounds) = map.boss_arena {
+        let seen = (0..=8).any(|x| {
+            (0..=8).any(|y| {
+                discovered(bounds.min + bounds.size() * Vec2::new(x as f32, y as f32) / 8.0)
+            })
+        });
+        if seen {
+            let min = to_map(bounds.min).as_ivec2();
+            let max = to_map(bounds.max).as_ivec2();
+            let size = (max - min).max(IVec2::ONE).as_uvec2();
+            draw_hollow_rect_mut(
+                &mut image,
+                rect::Rect::at(min.x, min.y).of_size(size.x, size.y),
+                Rgb([220, 30, 30]),
+            );
+            let center = to_map(bounds.center()).as_ivec2();
+            draw_filled_circle_mut(&mut image, (center.x, center.y), 3, Rgb([220, 30, 30]));
+        }
+    }
+
+    let markers = hearts
+        .iter()
+        .map(|transform| (transform, Rgb([255, 60, 80])))
+        .chain(
+            weapons
+                .iter()
+                .map(|transform| (transform, Rgb([255, 220, 40]))),
+        );
+    for (transform, color) in markers {
+        let pos = transform.translation().xz();
+        if discovered(pos) {
+            let pos = to_map(pos).as_ivec2();
+            draw_filled_circle_mut(&mut image, (pos.x, pos.y), 2, color);
+        }
+    }
+
+    let (player, player_transform) = player.into_inner();
+    let player_pos = to_map(player_transform.translation().xz());
+    if let Ok(camera) = transforms.get(player.world_camera) {
+        let look = player_pos + camera.forward().xz().normalize_or_zero() * 6.0;
+        draw_line_segment_mut(
+            &mut image,
+            (player_pos.x, player_pos.y),
+            (look.x, look.y),
+            Rgb([255, 255, 255]),
+        );
+    }
+    let pos = player_pos.as_ivec2();
+    draw_filled_circle_mut(&mut image, (pos.x, pos.y), 2, Rgb([255, 255, 255]));
+
+    let handle = map.image.clone();
+    if let Some(map_image) = images.get_mut(&handle) {
+        map_image.data = Some(
+            image
+                .pixels()
+                .flat_map(|pixel| [pixel.0[0], pixel.0[1], pixel.0[2], 255])
+                .collect(),
+        );
+    }
+}
+
 fn setup(mut commands: Commands, assets: Res<AssetServer>) {
     let font = assets.load("./fonts/NotoSerif-Regular.ttf");
     commands.spawn((
@@ -104,10 +375,59 @@ fn setup(mut commands: Commands, assets: Res<AssetServer>) {
             // inventory(),
             user_notify(font.clone()),
             // user_story(font.clone()),
+            minimap(),
+            full_map(),
         ],
     ));
 }
 
+fn minimap() -> impl Bundle {
+    let gap = 10.0;
+
+    (
+        Node {
+            width: Val::Px(MINIMAP),
+            height: Val::Px(MINIMAP),
+            position_type: PositionType::Absolute,
+            left: Val::Px(gap),
+            top: Val::Px(gap),
+            border: UiRect::all(Val::Px(2.0)),
+            ..Default::default()
+        },
+        BorderColor(Color::WHITE),
+        BackgroundColor(Color::BLACK),
+        MinimapView,
+        ImageNode::default(),
+    )
+}
+
+fn full_map() -> impl Bundle {
+    (
+        Node {
+            width: Val::Percent(100.0),
+            height: Val::Percent(100.0),
+            position_type: PositionType::Absolute,
+            display: Display::Flex,
+            justify_content: JustifyContent::Center,
+            align_items: AlignItems::Center,
+            ..Default::default()
+        },
+        BackgroundColor(Color::srgba(0.0, 0.0, 0.0, 0.8)),
+        FullMap,
+        Visibility::Hidden,
+        children![(
+            FullMapView,
+            Node {
+                height: Val::Percent(90.0),
+                border: UiRect::all(Val::Px(2.0)),
+                ..Default::default()
+            },
+            BorderColor(Color::WHITE),
+            ImageNode::default(),
+        )],
+    )
+}
+
 fn crosshair() -> impl Bundle {
     (
         Node {
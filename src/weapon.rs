@@ -1,12 +1,16 @@
 use std::{f32::consts::TAU, time::Duration};
 
 use bevy::{pbr::NotShadowCaster, prelude::*, render::view::RenderLayers};
+use rand::Rng;
 
 use crate::{
-    GameState,
+    GameRng, GameState,
+    enemy::perception::Noise,
+    faction::Faction,
+    floor::{FloorEntity, Floors, PartPickup},
     level::Level,
     player::Player,
-    projectile::{Damage, SpawnProjectile, bullet::Bullet},
+    projectile::{SpawnProjectile, bullet::Bullet},
     terrain::Physics,
     ui::UserNotify,
 };
@@ -98,7 +102,7 @@ fn drop_weapon(
             .remove_children(&[entity]);
         commands
             .entity(entity)
-            .insert(Transform::from_translation(player_pos))
+            .insert((FloorEntity, Transform::from_translation(player_pos)))
             .remove_recursive::<Children, NotShadowCaster>()
             .remove::<DropWeapon>();
     }
@@ -106,12 +110,16 @@ fn drop_weapon(
 
 fn pick_weapon(
     mut commands: Commands,
-    mut weapons: Query<(Entity, &mut Weapon), With<PickWeapon>>,
+    mut weapons: Query<(Entity, &mut Weapon, Option<&PartPickup>), With<PickWeapon>>,
     mut transforms: Query<&mut Transform>,
     children: Query<&Children>,
     player: Single<&mut Player>,
+    mut floors: ResMut<Floors>,
 ) {
-    for (entity, mut weapon) in &mut weapons {
+    for (entity, mut weapon, pickup) in &mut weapons {
+        if let Some(pickup) = pickup {
+            floors.take_pickup(pickup);
+        }
         if let Ok(mut transform) = transforms.get_mut(weapon.model) {
             transform.translation = weapon.offset;
             transform.rotation = Quat::default();
@@ -121,7 +129,7 @@ fn pick_weapon(
         commands
             .entity(entity)
             .insert(Transform::default())
-            .remove::<PickWeapon>();
+            .remove::<(PickWeapon, FloorEntity, PartPickup)>();
         for entity in children.iter_descendants(entity).chain([entity]) {
             commands.entity(entity).insert(NotShadowCaster);
         }
@@ -237,32 +245,40 @@ fn animate(
     }
 }
 
+// How far enemies hear a shot.
+const SHOT_NOISE: f32 = 30.0;
+
 fn shoot(
     mut commands: Commands,
     mut weapons: Query<&mut Weapon>,
     global_transforms: Query<&GlobalTransform>,
     mut transforms: Query<&mut Transform>,
     mut cameras: Query<(&Camera, &GlobalTransform)>,
-    player: Single<(&Player, &Physics)>,
+    player: Single<(Entity, &Player, &Physics, &Faction)>,
     level: Res<Level>,
     time: Res<Time>,
     game_state: Res<GameState>,
+    mut rng: ResMut<GameRng>,
 ) {
     if !matches!(*game_state, GameState::Running) {
         return;
     }
 
-    let (player, player_physics) = player.into_inner();
+    let (player_entity, player, player_physics, faction) = player.into_inner();
     let (camera, weapon_camera_transform) = cameras.get_mut(player.weapon_camera).unwrap();
     let mut camera_transform = transforms.get_mut(player.world_camera).unwrap();
 
-    let isec = level.raycast(
-        weapon_camera_transform.translation(),
-        weapon_camera_transform.forward(),
-        1.0,
-        100.0,
-        8,
-    );
+    let max_dist = 100.0;
+    let isec = level
+        .raycast(
+            weapon_camera_transform.translation(),
+            weapon_camera_transform.forward(),
+            max_dist,
+        )
+        .map_or(
+            weapon_camera_transform.translation() + weapon_camera_transform.forward() * max_dist,
+            |hit| hit.point,
+        );
 
     let min_pitch = 0.0140;
     let max_pitch = 0.0262;
@@ -284,8 +300,8 @@ fn shoot(
             // cameraYaw += Random(-horizontalKick, horizontalKick)
             // println!("SHOOT! {isec}");
             let (mut yaw, mut pitch, _) = camera_transform.rotation.to_euler(EulerRot::YXZ);
-            yaw += rand::random_range(-max_yaw..=max_yaw);
-            pitch += rand::random_range(min_pitch..=max_pitch);
+            yaw += rng.0.random_range(-max_yaw..=max_yaw);
+            pitch += rng.0.random_range(min_pitch..=max_pitch);
             let new_rotation =
                 Quat::from_axis_angle(Vec3::Y, yaw) * Quat::from_axis_angle(Vec3::X, pitch);
             camera_transform.rotation = camera_transform.rotation.slerp(new_rotation, 0.5);
@@ -293,8 +309,13 @@ fn shoot(
             weapon.projectile.spawn(
                 &mut commands,
                 Transform::from_translation(shoot_point).looking_at(isec, Vec3::Y),
-                Damage::Enemy,
+                faction.clone(),
+                player_entity,
             );
+            commands.send_event(Noise {
+                pos: shoot_point.xz(),
+                radius: SHOT_NOISE,
+            });
             weapon.shoot_timer += weapon.shoot_delay;
         }
         if weapon.shoot_timer > 0.0 {